@@ -1,30 +1,532 @@
 use super::verify_file;
-use clap::Parser;
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use clap::{ArgAction, Args, Parser};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
 use std::{fmt, path::Path, str::FromStr};
 
 #[derive(Debug, Clone, Copy)]
 pub enum OutputFormat {
     Json,
+    /// one JSON object per line
+    Ndjson,
     Yaml,
+    Toml,
+    /// a GitHub-flavored Markdown table
+    Markdown,
+    /// an HTML `<table>`, or a whole page with `--html-page`
+    Html,
 }
 
 /// Options for the csv subcommand, parsed to [`SubCommand::Csv(CsvOpts)`](SubCommand::Csv)
+/// without a nested subcommand it converts the csv file, e.g. `rcli csv -i input.csv -f yaml`
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CsvOpts {
-    #[arg(short, long, value_parser = verify_file)]
-    pub input: String,
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    /// input file, `-` reads from stdin. Repeat it or give a glob like `'exports/*.csv'` to read
+    /// several files, their columns are matched by name and missing cells are null
+    #[arg(short, long = "input", value_parser = verify_input, required = true)]
+    pub inputs: Vec<String>,
 
+    /// add a column with this name that holds the file every row came from
+    #[arg(long)]
+    pub source_column: Option<String>,
+
+    /// output file, `-` writes to stdout, defaults to `output.<format>`
     #[arg(short, long)]
     pub output: Option<String>,
 
     #[arg(short, long, value_parser = parse_format ,default_value = "json")]
     pub format: OutputFormat,
 
-    #[arg(short, long, default_value_t = ',')]
-    pub delimiter: char,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+
+    #[command(flatten)]
+    pub project: CsvProjectOpts,
+
+    /// only keep the rows matching this expression, e.g. `'Nationality == "AR" && Kit Number > 10'`
+    #[arg(long = "where")]
+    pub filter: Option<String>,
+
+    /// read an array of objects in this format from the input and write it back to csv
+    #[arg(long, value_parser = parse_format)]
+    pub from: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub flatten: CsvFlattenOpts,
+
+    /// with `-f html`, write a standalone page whose columns sort when their header is clicked
+    #[arg(long)]
+    pub html_page: bool,
+}
+
+/// Subcommands of the csv command, typed to [`CsvOpts::cmd`]
+#[derive(Debug, Parser)]
+pub enum CsvSubCommand {
+    #[command(about = "validate csv rows against a schema")]
+    Validate(CsvValidateOpts),
+    #[command(about = "run a SQL query over one or more csv files")]
+    Query(CsvQueryOpts),
+    #[command(about = "profile every column of a csv file")]
+    Stats(CsvStatsOpts),
+    #[command(about = "show csv as a table in the terminal")]
+    Show(CsvShowOpts),
+    #[command(about = "compare two csv files row by row, matched by key columns")]
+    Diff(CsvDiffOpts),
+    #[command(about = "join two csv files on key columns")]
+    Join(CsvJoinOpts),
+    #[command(about = "sort csv rows by one or more columns")]
+    Sort(CsvSortOpts),
+    #[command(about = "remove rows with the same key, keeping the first one")]
+    Dedup(CsvDedupOpts),
+    #[command(about = "split a csv file into parts by row count or column value")]
+    Split(CsvSplitOpts),
+    #[command(about = "generate fake csv rows from a spec")]
+    Gen(CsvGenOpts),
+    #[command(about = "hash, redact or truncate the values of sensitive columns")]
+    Mask(CsvMaskOpts),
+    #[command(about = "infer the schema of a csv file as a Rust struct, JSON Schema or YAML")]
+    Schema(CsvSchemaOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// YAML schema declaring the columns, their types and constraints
+    #[arg(short, long, value_parser = verify_file)]
+    pub schema: String,
+
+    /// write the valid rows to this file, `-` writes to stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    #[arg(short, long, value_parser = parse_format, default_value = "json")]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv query "SELECT Nationality, COUNT(*) FROM players GROUP BY Nationality" -i players=assets/test.csv`
+#[derive(Debug, Parser)]
+pub struct CsvQueryOpts {
+    /// SELECT statement, the tables are the input files
+    pub sql: String,
+
+    /// input file, repeat it to join files, the table name is the file stem unless given as `name=path`
+    #[arg(short, long = "input", value_parser = parse_table_input, required = true)]
+    pub inputs: Vec<(String, String)>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the result is printed as a table if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv stats -i assets/test.csv -f yaml`
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the report is printed as a table if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// number of most frequent values reported per column
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    /// distinct values counted exactly per column, above it the distinct count and the counts of
+    /// the most frequent values are estimates
+    #[arg(long, default_value_t = 100_000)]
+    pub distinct_limit: usize,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv show -i assets/test.csv --head 10`
+#[derive(Debug, Parser)]
+pub struct CsvShowOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// only show the first N rows
+    #[arg(long, conflicts_with = "tail")]
+    pub head: Option<usize>,
+
+    /// only show the last N rows
+    #[arg(long)]
+    pub tail: Option<usize>,
+
+    /// cells wider than this are truncated
+    #[arg(long, default_value_t = 40)]
+    pub max_width: usize,
+
+    /// table width, defaults to the terminal width
+    #[arg(long)]
+    pub width: Option<usize>,
+
+    /// print the whole table even if it is longer than the screen
+    #[arg(long)]
+    pub no_pager: bool,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv diff -a old.csv -b new.csv --key Name`
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    /// the old file
+    #[arg(short = 'a', long, value_parser = verify_file)]
+    pub old: String,
+
+    /// the new file
+    #[arg(short = 'b', long, value_parser = verify_file)]
+    pub new: String,
+
+    /// the columns that identify a row in both files
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, one record per change, a readable report if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv join -i players.csv -i teams.csv --on Nationality --kind left`
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    /// the left and the right file, the right one is held in memory
+    #[arg(short, long = "input", value_parser = verify_file, required = true)]
+    pub inputs: Vec<String>,
+
+    /// key columns, `Name` if both files call it the same or `left=right`
+    #[arg(long, value_delimiter = ',', value_parser = parse_join_key, required = true)]
+    pub on: Vec<(String, String)>,
+
+    #[arg(long, value_parser = parse_join_kind, default_value = "inner")]
+    pub kind: JoinKind,
+
+    /// prefix of the left columns whose name is also a right column, defaults to `<left file stem>.`
+    #[arg(long)]
+    pub left_prefix: Option<String>,
+
+    /// prefix of the right columns whose name is also a left column, defaults to `<right file stem>.`
+    #[arg(long)]
+    pub right_prefix: Option<String>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the joined rows are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv sort -i assets/test.csv --by "DOB:desc,Name" --types DOB=date`
+#[derive(Debug, Parser)]
+pub struct CsvSortOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// sort columns, each one `column`, `column:asc` or `column:desc`. Cells compare by the column
+    /// type, dates have to be declared with `--types`
+    #[arg(long, value_delimiter = ',', value_parser = parse_sort_key, required = true)]
+    pub by: Vec<(String, SortOrder)>,
 
-    #[arg(long, default_value_t = true)]
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the sorted rows are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// rows held in memory, larger inputs are sorted in runs of this size in temporary files
+    #[arg(long, default_value_t = 100_000)]
+    pub buffer_rows: usize,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv dedup -i assets/test.csv --key Name`
+#[derive(Debug, Parser)]
+pub struct CsvDedupOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// columns that identify a row, the whole row if not given
+    #[arg(short, long, value_delimiter = ',')]
+    pub key: Vec<String>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the rows are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// rows held in memory, larger inputs are sorted in runs of this size in temporary files
+    #[arg(long, default_value_t = 100_000)]
+    pub buffer_rows: usize,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv split -i assets/test.csv --by Nationality -o parts`
+#[derive(Debug, Parser)]
+pub struct CsvSplitOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// rows per part
+    #[arg(long, conflicts_with = "by", required_unless_present = "by", value_parser = clap::value_parser!(u64).range(1..))]
+    pub rows: Option<u64>,
+
+    /// write a part for every value of this column
+    #[arg(long)]
+    pub by: Option<String>,
+
+    /// directory the parts are written to, it is created if needed
+    #[arg(short, long, default_value = ".")]
+    pub output_dir: String,
+
+    /// file name of the parts, `{stem}` is the input file stem, `{part}` the part number or the
+    /// column value and `{ext}` the output format. Values that give the same file name, like
+    /// `a b` and `a_b`, get a `-2`, `-3`... suffix
+    #[arg(long, default_value = "{stem}-{part}.{ext}")]
+    pub pattern: String,
+
+    /// output format, the parts are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    /// rows held in memory by `--by`, larger inputs are grouped in temporary files
+    #[arg(long, default_value_t = 100_000)]
+    pub buffer_rows: usize,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv gen --spec assets/gen.yaml --rows 10000 --seed 42`
+#[derive(Debug, Parser)]
+pub struct CsvGenOpts {
+    /// YAML spec declaring the columns and their generators
+    #[arg(short, long, value_parser = verify_file)]
+    pub spec: String,
+
+    /// number of rows to generate
+    #[arg(short = 'n', long, default_value_t = 100)]
+    pub rows: u64,
+
+    /// seed of the random generator, the same seed and spec always give the same rows
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the rows are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+}
+
+/// e.g. `rcli csv mask -i assets/test.csv --hash Name --key key.txt --redact DOB --truncate Nationality:1`
+#[derive(Debug, Parser)]
+pub struct CsvMaskOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// replace the values of these columns with their keyed BLAKE3 hash, the same value and key
+    /// always give the same hash so masked files still join
+    #[arg(long, value_delimiter = ',', requires = "key")]
+    pub hash: Vec<String>,
+
+    /// the 32 byte key of `--hash`, e.g. from `rcli text generate`
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: Option<String>,
+
+    /// replace the values of these columns with `--redact-with`
+    #[arg(long, value_delimiter = ',')]
+    pub redact: Vec<String>,
+
+    /// the text redacted values are replaced with
+    #[arg(long, default_value = "***")]
+    pub redact_with: String,
+
+    /// keep the first characters of a column, e.g. `--truncate Nationality:1`
+    #[arg(long, value_delimiter = ',', value_parser = parse_truncate)]
+    pub truncate: Vec<(String, usize)>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    /// output format, the rows are written as csv if not given
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// e.g. `rcli csv schema -i assets/test.csv --emit rust --name Player`
+#[derive(Debug, Parser)]
+pub struct CsvSchemaOpts {
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// what to write: a serde struct, a JSON Schema or the YAML schema of `rcli csv validate`
+    #[arg(long, value_parser = parse_schema_format, default_value = "rust")]
+    pub emit: SchemaFormat,
+
+    /// name of the struct or title of the JSON Schema, taken from the file name if not given
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// output file, `-` writes to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+}
+
+/// Options that control how a csv file is parsed, shared by every command that reads csv.
+#[derive(Debug, Clone, Args)]
+pub struct CsvReaderOpts {
+    /// field delimiter, a single ASCII character or `\t` / `tab`
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: u8,
+
+    /// whether the first row is a header row, use `--header false` for headerless files
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
     pub header: bool,
+
+    /// column names to use instead of the header row (or of `col1`, `col2`, ... for headerless files)
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// encoding of the input, e.g. `utf-16le` or `windows-1252`. Without it a UTF-8 or UTF-16 BOM
+    /// picks the encoding and anything else is read as UTF-8, a BOM always wins
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// replace bytes that are not valid in the encoding with U+FFFD instead of failing, the lines
+    /// where this happened are reported
+    #[arg(long)]
+    pub lossy: bool,
+
+    /// what to do with malformed records (wrong number of fields, invalid bytes): `fail`, `skip`
+    /// them, or `collect` them in the `--rejects` file
+    #[arg(long, value_parser = parse_on_error, default_value = "fail")]
+    pub on_error: OnError,
+
+    /// csv file the malformed records are written to with `--on-error collect`, with their input
+    /// file, line, byte offset and the reason they were rejected
+    #[arg(long, default_value = "rejects.csv")]
+    pub rejects: String,
+}
+
+impl Default for CsvReaderOpts {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            header: true,
+            columns: Vec::new(),
+            encoding: None,
+            lossy: false,
+            on_error: OnError::Fail,
+            rejects: "rejects.csv".into(),
+        }
+    }
+}
+
+/// parse_encoding is a value parser for the [`CsvReaderOpts::encoding`] argument, it accepts the
+/// labels of the WHATWG Encoding Standard, e.g. `utf-8`, `utf-16le`, `windows-1252` or `latin1`.
+fn parse_encoding(label: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow::anyhow!("Unknown encoding {:?}", label))
+}
+
+fn parse_schema_format(format: &str) -> Result<SchemaFormat, anyhow::Error> {
+    format.parse()
+}
+
+fn parse_on_error(on_error: &str) -> Result<OnError, anyhow::Error> {
+    on_error.parse()
 }
 
 /// parse_format is a value parser for the [`CsvOpts::format`] argument, it will parse the format string to [`OutputFormat`].
@@ -33,6 +535,268 @@ fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
     format.parse::<OutputFormat>()
 }
 
+/// Options that control how cells are turned into typed JSON values.
+#[derive(Debug, Clone, Args)]
+pub struct CsvTypeOpts {
+    /// infer column types from a sample of rows, use `--infer false` to keep every cell a string
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    pub infer: bool,
+
+    /// number of rows scanned to infer the column types
+    #[arg(long, default_value_t = 1000)]
+    pub sample: usize,
+
+    /// override the inferred types, e.g. `--types "Kit Number=int,DOB=date"`
+    #[arg(long, value_delimiter = ',', value_parser = parse_column_type)]
+    pub types: Vec<(String, ColumnType)>,
+
+    /// parse a column with a strftime format, e.g. `--date "DOB=%m/%d/%Y"`, repeat it for more
+    /// columns. A format with a time makes ISO timestamps, the others ISO dates
+    #[arg(long = "date", value_parser = parse_date_format)]
+    pub dates: Vec<(String, String)>,
+
+    /// time zone of the timestamps without an offset, e.g. `Europe/Paris`, every timestamp is
+    /// written in it. Without it timestamps with an offset are written in UTC
+    #[arg(long, value_parser = parse_timezone)]
+    pub timezone: Option<Tz>,
+}
+
+impl Default for CsvTypeOpts {
+    fn default() -> Self {
+        Self {
+            infer: true,
+            sample: 1000,
+            types: Vec::new(),
+            dates: Vec::new(),
+            timezone: None,
+        }
+    }
+}
+
+/// Options that choose, rename and reorder the output columns, they all refer to the input column names.
+#[derive(Debug, Clone, Default, Args)]
+pub struct CsvProjectOpts {
+    /// only keep these columns, in this order
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    /// drop these columns
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// rename columns, e.g. `--rename "Kit Number=kit"`
+    #[arg(long, value_delimiter = ',', value_parser = parse_rename)]
+    pub rename: Vec<(String, String)>,
+
+    /// put these columns first, in this order, the other columns keep their order
+    #[arg(long, value_delimiter = ',')]
+    pub order: Vec<String>,
+}
+
+/// Options that control how nested values are flattened to columns when converting back to csv,
+/// and how `--unflatten` nests them again when converting csv.
+#[derive(Debug, Clone, Args)]
+pub struct CsvFlattenOpts {
+    /// separator used to join the keys of nested objects, e.g. `address.city`
+    #[arg(long, default_value = ".")]
+    pub separator: String,
+
+    /// rebuild nested objects and arrays from headers like `address.city` and `tags[0]`
+    #[arg(long)]
+    pub unflatten: bool,
+
+    /// maximum nesting depth to flatten, deeper values are written as JSON text
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+}
+
+impl Default for CsvFlattenOpts {
+    fn default() -> Self {
+        Self {
+            separator: ".".into(),
+            unflatten: false,
+            max_depth: None,
+        }
+    }
+}
+
+/// How rows without a match in the other file are kept by a join.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// only rows that match
+    Inner,
+    /// every left row
+    Left,
+    /// every right row
+    Right,
+    /// every row of both files
+    Outer,
+}
+
+/// What a csv reader does with a malformed record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    Fail,
+    Skip,
+    /// skip the record and write it to the rejects file
+    Collect,
+}
+
+/// The direction of a sort column, nulls come first in ascending order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// What `rcli csv schema` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// a struct with serde attributes
+    Rust,
+    JsonSchema,
+    /// the schema read by `rcli csv validate`
+    Yaml,
+}
+
+/// The type of a csv column, either inferred from the data or declared with `--types`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Date,
+    /// a date and time, written as ISO 8601
+    Timestamp,
+    String,
+}
+
+/// parse_column_type is a value parser for the [`CsvTypeOpts::types`] argument,
+/// it will parse `column=type` to a tuple of the column name and [`ColumnType`].
+fn parse_column_type(s: &str) -> Result<(String, ColumnType), anyhow::Error> {
+    let (column, ty) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected column=type, got {:?}", s))?;
+    Ok((column.trim().to_string(), ty.trim().parse()?))
+}
+
+/// parse_date_format is a value parser for the [`CsvTypeOpts::dates`] argument, it parses
+/// `column=format` and checks the format, a format may contain `=` so the first one splits.
+fn parse_date_format(s: &str) -> Result<(String, String), anyhow::Error> {
+    let (column, format) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected column=format, got {:?}", s))?;
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        anyhow::bail!("Invalid date format {:?}", format);
+    }
+    Ok((column.trim().to_string(), format.to_string()))
+}
+
+/// parse_timezone is a value parser for the [`CsvTypeOpts::timezone`] argument, it takes IANA
+/// names like `Europe/Paris` or `UTC`.
+fn parse_timezone(s: &str) -> Result<Tz, anyhow::Error> {
+    s.parse().map_err(|_| {
+        anyhow::anyhow!(
+            "Unknown time zone {:?}, expected a name like Europe/Paris",
+            s
+        )
+    })
+}
+
+/// parse_rename is a value parser for the [`CsvProjectOpts::rename`] argument, it parses `old=new`.
+fn parse_rename(s: &str) -> Result<(String, String), anyhow::Error> {
+    match s.rsplit_once('=') {
+        Some((old, new)) if !new.trim().is_empty() => {
+            Ok((old.trim().to_string(), new.trim().to_string()))
+        }
+        _ => Err(anyhow::anyhow!("Expected old=new, got {:?}", s)),
+    }
+}
+
+/// parse_table_input is a value parser for the [`CsvQueryOpts::inputs`] argument,
+/// it parses `name=path` or a path whose file stem is the table name.
+fn parse_table_input(s: &str) -> Result<(String, String), anyhow::Error> {
+    let (name, path) = match s.split_once('=') {
+        Some((name, path)) if !Path::new(s).exists() => (name.trim().to_string(), path),
+        _ => {
+            let stem = Path::new(s).file_stem().and_then(|stem| stem.to_str());
+            (stem.unwrap_or("stdin").to_string(), s)
+        }
+    };
+    if name.is_empty() {
+        anyhow::bail!("Expected name=path, got {:?}", s);
+    }
+    Ok((name, verify_file(path).map_err(anyhow::Error::msg)?))
+}
+
+/// verify_input is a value parser for the [`CsvOpts::inputs`] argument, a glob pattern is kept as
+/// it is and expanded when the files are opened
+fn verify_input(input: &str) -> Result<String, anyhow::Error> {
+    if input.contains(['*', '?', '[']) {
+        glob::Pattern::new(input)?;
+        return Ok(input.to_string());
+    }
+    verify_file(input).map_err(anyhow::Error::msg)
+}
+
+/// parse_join_key is a value parser for the [`CsvJoinOpts::on`] argument, it parses `left=right` or
+/// a column name that is the same in both files.
+fn parse_join_key(s: &str) -> Result<(String, String), anyhow::Error> {
+    let (left, right) = s.split_once('=').unwrap_or((s, s));
+    let (left, right) = (left.trim(), right.trim());
+    if left.is_empty() || right.is_empty() {
+        anyhow::bail!("Expected column or left=right, got {:?}", s);
+    }
+    Ok((left.to_string(), right.to_string()))
+}
+
+/// parse_sort_key is a value parser for the [`CsvSortOpts::by`] argument, it parses `column` or
+/// `column:asc|desc`. Only a known order after the last `:` is split off, so `a:b` is a column.
+fn parse_sort_key(s: &str) -> Result<(String, SortOrder), anyhow::Error> {
+    let (column, order) = match s.rsplit_once(':') {
+        Some((column, order)) => match order.trim().parse() {
+            Ok(order) => (column, order),
+            Err(_) => (s, SortOrder::Asc),
+        },
+        None => (s, SortOrder::Asc),
+    };
+    let column = column.trim();
+    if column.is_empty() {
+        anyhow::bail!("Expected column or column:desc, got {:?}", s);
+    }
+    Ok((column.to_string(), order))
+}
+
+/// parse_truncate is a value parser for the [`CsvMaskOpts::truncate`] argument, it parses
+/// `column:length`.
+fn parse_truncate(s: &str) -> Result<(String, usize), anyhow::Error> {
+    match s.rsplit_once(':') {
+        Some((column, len)) if !column.trim().is_empty() => {
+            Ok((column.trim().to_string(), len.trim().parse()?))
+        }
+        _ => Err(anyhow::anyhow!("Expected column:length, got {:?}", s)),
+    }
+}
+
+fn parse_join_kind(kind: &str) -> Result<JoinKind, anyhow::Error> {
+    kind.parse()
+}
+
+/// parse_delimiter is a value parser for the [`CsvReaderOpts::delimiter`] argument,
+/// the csv parser only accepts a single byte, so the delimiter has to be an ASCII character.
+fn parse_delimiter(delimiter: &str) -> Result<u8, anyhow::Error> {
+    match delimiter {
+        "\\t" | "tab" => Ok(b'\t'),
+        s if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(anyhow::anyhow!(
+            "Delimiter must be a single ASCII character, got {:?}",
+            delimiter
+        )),
+    }
+}
+
 // From is a trait that can be implemented for a type to convert it to another type
 // in here, we implement From<OutputFormat> for &'static str, which means we can convert OutputFormat to &'static str
 /// Implement the conversion from [`OutputFormat`] to `&'static str`, this will be used to convert the format to a string.
@@ -40,7 +804,11 @@ impl From<OutputFormat> for &'static str {
     fn from(format: OutputFormat) -> Self {
         match format {
             OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
         }
     }
 }
@@ -53,7 +821,11 @@ impl FromStr for OutputFormat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
             "yaml" => Ok(OutputFormat::Yaml),
+            "toml" => Ok(OutputFormat::Toml),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
@@ -64,3 +836,257 @@ impl fmt::Display for OutputFormat {
         write!(f, "{}", Into::<&str>::into(*self))
     }
 }
+
+impl FromStr for JoinKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "right" => Ok(JoinKind::Right),
+            "outer" | "full" => Ok(JoinKind::Outer),
+            _ => Err(anyhow::anyhow!("Invalid join kind {:?}", s)),
+        }
+    }
+}
+
+impl From<JoinKind> for &'static str {
+    fn from(kind: JoinKind) -> Self {
+        match kind {
+            JoinKind::Inner => "inner",
+            JoinKind::Left => "left",
+            JoinKind::Right => "right",
+            JoinKind::Outer => "outer",
+        }
+    }
+}
+
+impl fmt::Display for JoinKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for OnError {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "collect" => Ok(OnError::Collect),
+            _ => Err(anyhow::anyhow!("Invalid error mode {:?}", s)),
+        }
+    }
+}
+
+impl From<OnError> for &'static str {
+    fn from(on_error: OnError) -> Self {
+        match on_error {
+            OnError::Fail => "fail",
+            OnError::Skip => "skip",
+            OnError::Collect => "collect",
+        }
+    }
+}
+
+impl fmt::Display for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(anyhow::anyhow!("Invalid sort order {:?}", s)),
+        }
+    }
+}
+
+impl From<SortOrder> for &'static str {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for SchemaFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(SchemaFormat::Rust),
+            "json-schema" => Ok(SchemaFormat::JsonSchema),
+            "yaml" => Ok(SchemaFormat::Yaml),
+            _ => Err(anyhow::anyhow!("Invalid schema format {:?}", s)),
+        }
+    }
+}
+
+impl From<SchemaFormat> for &'static str {
+    fn from(format: SchemaFormat) -> Self {
+        match format {
+            SchemaFormat::Rust => "rust",
+            SchemaFormat::JsonSchema => "json-schema",
+            SchemaFormat::Yaml => "yaml",
+        }
+    }
+}
+
+impl fmt::Display for SchemaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" => Ok(ColumnType::Null),
+            "bool" => Ok(ColumnType::Bool),
+            "int" => Ok(ColumnType::Int),
+            "float" => Ok(ColumnType::Float),
+            "date" => Ok(ColumnType::Date),
+            "timestamp" => Ok(ColumnType::Timestamp),
+            "string" => Ok(ColumnType::String),
+            _ => Err(anyhow::anyhow!("Invalid column type {:?}", s)),
+        }
+    }
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::Null => "null",
+            ColumnType::Bool => "bool",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Date => "date",
+            ColumnType::Timestamp => "timestamp",
+            ColumnType::String => "string",
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(";").unwrap(), b';');
+        assert_eq!(parse_delimiter("\t").unwrap(), b'\t');
+        assert_eq!(parse_delimiter("\\t").unwrap(), b'\t');
+        assert_eq!(parse_delimiter("tab").unwrap(), b'\t');
+        assert!(parse_delimiter("::").is_err());
+        assert!(parse_delimiter("§").is_err());
+    }
+
+    #[test]
+    fn test_header_can_be_turned_off() {
+        let opts = CsvOpts::try_parse_from(["csv", "-i", "-", "--header", "false"]).unwrap();
+        assert!(!opts.reader.header);
+        let opts = CsvOpts::try_parse_from(["csv", "-i", "-", "--columns", "a,b,c"]).unwrap();
+        assert!(opts.reader.header);
+        assert_eq!(opts.reader.columns, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_parse_types() {
+        let opts =
+            CsvOpts::try_parse_from(["csv", "-i", "-", "--types", "Kit Number=int,DOB=date"])
+                .unwrap();
+        assert_eq!(
+            opts.types.types,
+            [
+                ("Kit Number".to_string(), ColumnType::Int),
+                ("DOB".to_string(), ColumnType::Date)
+            ]
+        );
+        assert!(parse_column_type("DOB").is_err());
+        assert!(parse_column_type("DOB=datetime").is_err());
+
+        let opts = CsvOpts::try_parse_from([
+            "csv",
+            "-i",
+            "-",
+            "--date",
+            "DOB=%b %d, %Y",
+            "--timezone",
+            "Europe/Paris",
+        ])
+        .unwrap();
+        assert_eq!(
+            opts.types.dates,
+            [("DOB".to_string(), "%b %d, %Y".to_string())]
+        );
+        assert_eq!(opts.types.timezone, Some(Tz::Europe__Paris));
+        assert!(parse_date_format("DOB=%Q").is_err());
+        assert!(parse_timezone("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_convert_and_subcommands() {
+        let opts = CsvOpts::try_parse_from(["csv", "-i", "-", "-f", "yaml"]).unwrap();
+        assert!(opts.cmd.is_none());
+        let opts =
+            CsvOpts::try_parse_from(["csv", "validate", "-i", "-", "-s", "Cargo.toml"]).unwrap();
+        assert!(matches!(opts.cmd, Some(CsvSubCommand::Validate(_))));
+    }
+
+    #[test]
+    fn test_parse_table_input() {
+        assert_eq!(
+            parse_table_input("assets/test.csv").unwrap(),
+            ("test".to_string(), "assets/test.csv".to_string())
+        );
+        assert_eq!(
+            parse_table_input("players=assets/test.csv").unwrap(),
+            ("players".to_string(), "assets/test.csv".to_string())
+        );
+        assert!(parse_table_input("players=missing.csv").is_err());
+        assert!(parse_table_input("=assets/test.csv").is_err());
+    }
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_eq!(
+            parse_sort_key("DOB:desc").unwrap(),
+            ("DOB".to_string(), SortOrder::Desc)
+        );
+        assert_eq!(
+            parse_sort_key("Name").unwrap(),
+            ("Name".to_string(), SortOrder::Asc)
+        );
+        assert_eq!(
+            parse_sort_key("a:b").unwrap(),
+            ("a:b".to_string(), SortOrder::Asc)
+        );
+        assert!(parse_sort_key(":desc").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("utf-16le").unwrap(), encoding_rs::UTF_16LE);
+        // latin1 is decoded as windows-1252 like browsers do
+        assert_eq!(parse_encoding("latin1").unwrap(), encoding_rs::WINDOWS_1252);
+        assert!(parse_encoding("klingon").is_err());
+    }
+}
@@ -4,15 +4,18 @@ mod genpass;
 mod http;
 mod text;
 
-use anyhow::Ok;
 use clap::Parser;
-use csv::CsvOpts;
 use genpass::GenPassOpts;
 use std::path::{Path, PathBuf};
 
 pub use self::{
     base64::{Base64Format, Base64SubCommand},
-    csv::OutputFormat,
+    csv::{
+        ColumnType, CsvDedupOpts, CsvDiffOpts, CsvFlattenOpts, CsvGenOpts, CsvJoinOpts,
+        CsvMaskOpts, CsvOpts, CsvProjectOpts, CsvQueryOpts, CsvReaderOpts, CsvSchemaOpts,
+        CsvShowOpts, CsvSortOpts, CsvSplitOpts, CsvStatsOpts, CsvSubCommand, CsvTypeOpts,
+        CsvValidateOpts, JoinKind, OnError, OutputFormat, SchemaFormat, SortOrder,
+    },
     text::{TextSignFormat, TextSubCommand},
 };
 
@@ -26,6 +29,8 @@ pub struct Opts {
 }
 
 /// Subcommands of the rcli command, typed to [`Opts::cmd`]
+// the options are parsed once per run, so the size difference between variants does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 pub enum SubCommand {
     #[command(name = "csv", about = "show csv or convert csv to other formats")]
@@ -43,7 +48,6 @@ pub enum SubCommand {
 /// verify_input_file is a value parser for the [`CsvOpts::input`] argument, it will check if the file exists.  
 /// Please note that this function is only check for file name, not the content of the file, it will
 /// return ok if the file exists, otherwise it will return an error.
-
 // static: if there any thing has the same lifetime with process, it can be static
 fn verify_file(filename: &str) -> Result<String, &'static str> {
     //if  input is "-" or file exists
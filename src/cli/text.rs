@@ -1,10 +1,6 @@
 use super::{verify_file, verify_path};
 use clap::Parser;
-use std::{
-    fmt::{self, write},
-    path::PathBuf,
-    str::FromStr,
-};
+use std::{fmt, path::PathBuf, str::FromStr};
 
 #[derive(Debug, Parser)]
 pub enum TextSubCommand {
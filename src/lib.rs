@@ -3,10 +3,17 @@ mod process;
 mod utils;
 
 pub use cli::{
-    Base64Format, Base64SubCommand, Opts, OutputFormat, SubCommand, TextSignFormat, TextSubCommand,
+    Base64Format, Base64SubCommand, ColumnType, CsvDedupOpts, CsvDiffOpts, CsvFlattenOpts,
+    CsvGenOpts, CsvJoinOpts, CsvMaskOpts, CsvOpts, CsvProjectOpts, CsvQueryOpts, CsvReaderOpts,
+    CsvSchemaOpts, CsvShowOpts, CsvSortOpts, CsvSplitOpts, CsvStatsOpts, CsvSubCommand,
+    CsvTypeOpts, CsvValidateOpts, JoinKind, OnError, Opts, OutputFormat, SchemaFormat, SortOrder,
+    SubCommand, TextSignFormat, TextSubCommand,
 };
 pub use process::{
-    process_csv, process_decode, process_encode, process_genpass, process_text_generate,
-    process_text_sign, process_text_verify,
+    process_csv, process_csv_dedup, process_csv_diff, process_csv_gen, process_csv_join,
+    process_csv_mask, process_csv_query, process_csv_reverse, process_csv_schema, process_csv_show,
+    process_csv_sort, process_csv_split, process_csv_stats, process_csv_validate, process_decode,
+    process_encode, process_genpass, process_text_generate, process_text_sign, process_text_verify,
+    ColumnStats, ConvertSummary, DiffSummary, TopValue, ValidationReport, Violation,
 };
 pub use utils::*;
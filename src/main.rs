@@ -1,11 +1,14 @@
 use clap::Parser;
 // rcli csv -i input.csv -o output.json --header -d ','
 use rcli::{
-    process_csv, process_decode, process_encode, process_genpass, process_text_generate,
-    process_text_sign, process_text_verify, Base64SubCommand, Opts, SubCommand, TextSignFormat,
-    TextSubCommand,
+    process_csv, process_csv_dedup, process_csv_diff, process_csv_gen, process_csv_join,
+    process_csv_mask, process_csv_query, process_csv_reverse, process_csv_schema, process_csv_show,
+    process_csv_sort, process_csv_split, process_csv_stats, process_csv_validate, process_decode,
+    process_encode, process_genpass, process_text_generate, process_text_sign, process_text_verify,
+    Base64SubCommand, CsvSubCommand, OnError, Opts, SubCommand, TextSignFormat, TextSubCommand,
 };
 
+use std::fs;
 use zxcvbn::zxcvbn;
 
 fn main() -> anyhow::Result<()> {
@@ -13,14 +16,97 @@ fn main() -> anyhow::Result<()> {
     // which is the struct of CsvOpts
     let opts = Opts::parse();
     match opts.cmd {
-        SubCommand::Csv(opts) => {
-            let output = if let Some(output) = opts.output {
-                output.clone()
-            } else {
-                format!("output.{}", opts.format)
-            };
-            process_csv(&opts.input, output, opts.format)?;
-        }
+        SubCommand::Csv(opts) => match &opts.cmd {
+            Some(CsvSubCommand::Validate(opts)) => {
+                let report = process_csv_validate(opts, |violation| eprintln!("{}", violation))?;
+                eprintln!(
+                    "{} of {} row(s) valid, {} violation(s)",
+                    report.valid_rows, report.rows, report.violations
+                );
+                if report.violations > 0 {
+                    anyhow::bail!("validation failed");
+                }
+            }
+            Some(CsvSubCommand::Query(opts)) => {
+                for conflict in process_csv_query(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Stats(opts)) => {
+                for conflict in process_csv_stats(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Show(opts)) => {
+                for conflict in process_csv_show(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Join(opts)) => {
+                for conflict in process_csv_join(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Sort(opts)) => {
+                for conflict in process_csv_sort(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Dedup(opts)) => {
+                let (removed, conflicts) = process_csv_dedup(opts)?;
+                for conflict in conflicts {
+                    eprintln!("type conflict: {}", conflict);
+                }
+                eprintln!("{} duplicate row(s) removed", removed);
+            }
+            Some(CsvSubCommand::Split(opts)) => {
+                let (parts, conflicts) = process_csv_split(opts)?;
+                for conflict in conflicts {
+                    eprintln!("type conflict: {}", conflict);
+                }
+                eprintln!("{} part(s) written", parts);
+            }
+            Some(CsvSubCommand::Gen(opts)) => {
+                let rows = process_csv_gen(opts)?;
+                eprintln!("{} row(s) generated", rows);
+            }
+            Some(CsvSubCommand::Mask(opts)) => {
+                for conflict in process_csv_mask(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Schema(opts)) => {
+                for conflict in process_csv_schema(opts)? {
+                    eprintln!("type conflict: {}", conflict);
+                }
+            }
+            Some(CsvSubCommand::Diff(opts)) => {
+                let summary = process_csv_diff(opts)?;
+                eprintln!("{}", summary);
+            }
+            None => match opts.from {
+                // --from reads json/yaml/toml and writes csv, the default output is output.csv
+                Some(from) => {
+                    let output = opts.output.clone().unwrap_or_else(|| "output.csv".into());
+                    process_csv_reverse(&opts, from, &output)?;
+                }
+                None => {
+                    let output = if let Some(output) = &opts.output {
+                        output.clone()
+                    } else {
+                        format!("output.{}", opts.format)
+                    };
+                    let summary = process_csv(&opts, &output)?;
+                    // type conflicts are not fatal, they are reported in stderr like the password strength
+                    for conflict in &summary.conflicts {
+                        eprintln!("type conflict: {}", conflict);
+                    }
+                    if opts.reader.on_error != OnError::Fail {
+                        eprintln!("{}", summary);
+                    }
+                }
+            },
+        },
         SubCommand::GenPass(opts) => {
             let password = process_genpass(
                 opts.length,
@@ -5,7 +5,6 @@ use base64::{
     engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
     Engine as _,
 };
-use serde::de;
 
 /// process_encode will encode the input file to base64 and print the result to stdout.
 /// input is a file path, if input is "-", it will read from stdin.
@@ -57,7 +56,7 @@ mod tests {
 
     #[test]
     fn test_process_decode() {
-        let input = "Cargo.toml";
+        let input = "assets/b64.txt";
         let format = Base64Format::UrlSafe;
         process_decode(input, format).unwrap();
     }
@@ -2,14 +2,20 @@
 // and then cast to output.
 // Serilize is a trait that can be derived to make a serde data structure serializable to JSON, XML, etc.
 // Deserialize is a trait that can be derived to make a struct deserializable to serde data structure.
-use anyhow;
-use csv::Reader;
+use csv::StringRecord;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::fs;
+use serde_json::Value;
+use std::fmt;
 
-use crate::cli::OutputFormat;
+use super::csv_expr::Filter;
+use super::csv_project::Projection;
+use super::csv_reader::{expand_inputs, CsvSource, Rejects};
+use super::csv_types::{infer_types, TypeConflict, TypeConverter};
+use super::csv_unflatten::Unflatten;
+use super::csv_writer::{create_html_writer, create_row_writer};
+use crate::{cli::CsvOpts, get_writer, ColumnType, OutputFormat};
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 /// Player struct that will be used to parse the CSV file.
@@ -27,8 +33,19 @@ struct Player {
     kit: u8,
 }
 
-pub fn process_csv(input: &str, output: String, format: OutputFormat) -> anyhow::Result<()> {
-    // Reader::from_path is a function from the csv module
+/// ConvertSummary is what `process_csv` reports back: how many rows were written, how many
+/// malformed records were skipped with `--on-error`, and the type conflicts.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConvertSummary {
+    pub converted: usize,
+    pub rejected: usize,
+    pub conflicts: Vec<TypeConflict>,
+}
+
+/// process_csv converts the csv input to the output format, the cells are typed by
+/// [`TypeConverter`], and the type conflicts found along the way are returned to the caller.
+pub fn process_csv(opts: &CsvOpts, output: &str) -> anyhow::Result<ConvertSummary> {
+    // CsvSource builds the csv reader from the delimiter/header/columns options
     // ? is to do a try, if the result is an error, it will return the error
     /* it just like the following code
      * match reader {
@@ -37,30 +54,241 @@ pub fn process_csv(input: &str, output: String, format: OutputFormat) -> anyhow:
      */
     // a benefit of anyhow is it can convert any error to anyhow::Error
     // so error in here can be converted to anyhow::Error
-    let mut reader = Reader::from_path(input)?;
-    // ret is a Vec<Player> that will be used to store the parsed CSV data
-    let mut ret = Vec::with_capacity(128);
-    let headers = reader.headers()?.clone();
-    //diffrence between deserialize and records is deserialize will return a single record
-    // records will return an iterator of records
-    for result in reader.records() {
-        let record = result?;
-        // headers.iter() -> it will return an iterator of headers
-        // zip(record.iter()) -> it will return an iterator of tuple that combine the headers and record iterator [(header, record), ...]
-        // collect::<Value>() -> it will convert the iterator of tuple to a JSON Value
-        let json_value = headers
-            .iter()
-            .zip(record.iter())
-            .collect::<serde_json::Value>();
-        ret.push(json_value);
+    let rejects = Rejects::open(&opts.reader)?;
+    let mut inputs = expand_inputs(&opts.inputs)?
+        .into_iter()
+        .map(|path| {
+            let source = CsvSource::open_with_rejects(&path, &opts.reader, rejects.as_ref())?;
+            Ok((path, source))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = merge_headers(&inputs, opts.source_column.as_deref())?;
+
+    // the first rows of every file are read ahead to infer the column types, then converted like
+    // the rest, the cells are moved to the merged columns first
+    let mut layouts = Vec::with_capacity(inputs.len());
+    let mut samples = Vec::with_capacity(inputs.len());
+    for (path, source) in inputs.iter_mut() {
+        let layout = Layout::new(
+            path,
+            source.headers(),
+            &headers,
+            opts.source_column.is_some(),
+        );
+        let sample = source
+            .by_ref()
+            .take(opts.types.sample)
+            .map(|record| Ok(layout.remap(record?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        layouts.push(layout);
+        samples.push(sample);
+    }
+    let mut types = infer_types(&headers, &samples.concat(), &opts.types)?;
+    if opts.source_column.is_some() {
+        types[headers.len() - 1] = ColumnType::String;
     }
+    let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+    let projection = Projection::new(&headers, &opts.project)?;
+    let filter = match &opts.filter {
+        Some(source) => Some(Filter::new(source, &headers)?),
+        None => None,
+    };
+    // the nesting is resolved from the output names up front, so conflicting paths fail early
+    let unflatten = if opts.flatten.unflatten {
+        Some(Unflatten::new(
+            &projection.names(),
+            &opts.flatten.separator,
+        )?)
+    } else {
+        None
+    };
+    // typed values of the columns the filter reads, the projection reuses them
+    let mut values = vec![Value::Null; headers.len()];
 
-    // content is a string that will be used to store the serialized data
-    // serde_json, serde_yaml, and toml are used to serialize the data, it will convert the data to a string
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+    // rows are written as soon as they are converted, so memory stays the same whatever the input size
+    // serde_json, serde_yaml, and toml are used to serialize the rows, see csv_writer
+    // if output is "-", it will write to stdout, so conversions compose with other tools in a pipeline
+    let names = match &unflatten {
+        Some(unflatten) => unflatten.names(),
+        None => projection.names(),
     };
-    fs::write(output, content)?;
-    Ok(())
+    let mut writer = match opts.format {
+        OutputFormat::Html => create_html_writer(get_writer(output)?, &names, opts.html_page),
+        format => create_row_writer(format, get_writer(output)?, &names),
+    };
+    let mut converted = 0;
+    for ((_, source), (layout, sample)) in inputs.iter_mut().zip(layouts.iter().zip(samples)) {
+        let rest = source
+            .by_ref()
+            .map(|record| record.map(|r| layout.remap(r)));
+        for result in sample.into_iter().map(Ok).chain(rest) {
+            let record = result?;
+            if let Some(filter) = &filter {
+                for &idx in filter.columns() {
+                    values[idx] = layout.cell(&mut converter, &headers, &record, idx);
+                }
+                if !filter.matches(&values) {
+                    continue;
+                }
+            }
+            // the projection only asks for the cells of the output columns, in output order,
+            // and pairs every typed value with its (renamed) column name to build the JSON object
+            let json_value = projection.row(|idx| match &filter {
+                Some(filter) if filter.columns().contains(&idx) => values[idx].take(),
+                _ => layout.cell(&mut converter, &headers, &record, idx),
+            });
+            let json_value = match &unflatten {
+                Some(unflatten) => unflatten.row(json_value),
+                None => json_value,
+            };
+            writer.write_row(&json_value)?;
+            converted += 1;
+        }
+    }
+    writer.finish()?;
+    Ok(ConvertSummary {
+        converted,
+        rejected: inputs.iter().map(|(_, source)| source.rejected()).sum(),
+        conflicts: converter.conflicts(),
+    })
+}
+
+/// Layout moves the cells of one input file to the merged columns of all inputs
+struct Layout {
+    path: String,
+    // the position in the file of every merged column, None if the file does not have it
+    columns: Vec<Option<usize>>,
+    source_column: bool,
+    // the file has the merged columns in the same order, its records are used as they are
+    identity: bool,
+}
+
+/// the columns of all files in the order they first appear, then the `--source-column`
+fn merge_headers(
+    inputs: &[(String, CsvSource)],
+    source_column: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let mut headers: Vec<String> = Vec::new();
+    for (_, source) in inputs {
+        for header in source.headers() {
+            if !headers.contains(header) {
+                headers.push(header.clone());
+            }
+        }
+    }
+    if let Some(name) = source_column {
+        if headers.iter().any(|h| h == name) {
+            anyhow::bail!(
+                "--source-column {:?} is already a column of the input",
+                name
+            );
+        }
+        headers.push(name.to_string());
+    }
+    Ok(headers)
+}
+
+impl Layout {
+    fn new(path: &str, file: &[String], merged: &[String], source_column: bool) -> Self {
+        let merged = &merged[..merged.len() - source_column as usize];
+        let columns = merged
+            .iter()
+            .map(|h| file.iter().position(|f| f == h))
+            .collect::<Vec<_>>();
+        Self {
+            path: path.to_string(),
+            identity: !source_column && file == merged,
+            columns,
+            source_column,
+        }
+    }
+
+    /// the record with its cells in the merged columns, missing cells are empty
+    fn remap(&self, record: StringRecord) -> StringRecord {
+        if self.identity {
+            return record;
+        }
+        let mut ret = StringRecord::with_capacity(record.as_slice().len(), self.columns.len() + 1);
+        for idx in &self.columns {
+            ret.push_field(idx.and_then(|i| record.get(i)).unwrap_or_default());
+        }
+        if self.source_column {
+            ret.push_field(&self.path);
+        }
+        ret.set_position(record.position().cloned());
+        ret
+    }
+
+    /// the typed cell of a merged column, null if the file does not have the column
+    fn cell(
+        &self,
+        converter: &mut TypeConverter,
+        headers: &[String],
+        record: &StringRecord,
+        idx: usize,
+    ) -> Value {
+        match self.columns.get(idx) {
+            Some(None) => Value::Null,
+            _ => converter.convert_cell(headers, record, idx),
+        }
+    }
+}
+
+impl fmt::Display for ConvertSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} row(s) converted, {} rejected",
+            self.converted, self.rejected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CsvReaderOpts;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn input(path: &str, content: &str) -> (String, CsvSource) {
+        let reader = Box::new(Cursor::new(content.to_string()));
+        let source = CsvSource::from_reader(reader, &CsvReaderOpts::default()).unwrap();
+        (path.to_string(), source)
+    }
+
+    #[test]
+    fn test_merge_inputs() {
+        let inputs = [
+            input("jan.csv", "Name,Kit\nDennis,17\n"),
+            input("feb.csv", "Kit,Name,Team\n92,Glenn,Roma\n"),
+        ];
+        let headers = merge_headers(&inputs, Some("file")).unwrap();
+        assert_eq!(headers, ["Name", "Kit", "Team", "file"]);
+        assert!(merge_headers(&inputs, Some("Kit")).is_err());
+
+        let mut converter = TypeConverter::new(vec![ColumnType::String; 4], false);
+        let rows = inputs
+            .into_iter()
+            .flat_map(|(path, source)| {
+                let layout = Layout::new(&path, source.headers(), &headers, true);
+                source
+                    .map(|record| {
+                        let record = layout.remap(record.unwrap());
+                        (0..headers.len())
+                            .map(|idx| layout.cell(&mut converter, &headers, &record, idx))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // with --infer false the empty cells stay strings, but the missing Team is null
+        assert_eq!(
+            rows,
+            [
+                vec![json!("Dennis"), json!("17"), json!(null), json!("jan.csv")],
+                vec![json!("Glenn"), json!("92"), json!("Roma"), json!("feb.csv")],
+            ]
+        );
+    }
 }
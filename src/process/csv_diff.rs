@@ -0,0 +1,477 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Write,
+};
+
+use super::csv_reader::{CsvSource, Rejects};
+use super::csv_types::{
+    compare_values, file_types, infer_types, keys_as_text, text_key, value_key, value_text,
+    TypeConverter,
+};
+use super::csv_writer::create_row_writer;
+use crate::{get_writer, ColumnType, CsvDiffOpts, CsvReaderOpts, CsvTypeOpts};
+
+/// DiffSummary counts the rows of every kind of change, the caller prints it after the report.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    pub unchanged: usize,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+}
+
+/// a row that was added, removed or modified, identified by its key cells
+#[derive(Debug, Clone, PartialEq)]
+struct RowChange {
+    kind: ChangeKind,
+    key: Vec<(String, Value)>,
+    /// the other cells of an added or removed row
+    row: Vec<(String, Value)>,
+    /// the cells of a modified row that changed, as (column, before, after)
+    cells: Vec<(String, Value, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// a csv file read in memory with typed cells, and the line every row starts at
+struct Loaded {
+    headers: Vec<String>,
+    types: Vec<ColumnType>,
+    rows: Vec<(u64, Vec<Value>)>,
+}
+
+/// process_csv_diff matches the rows of the old and new file by the key columns and writes the
+/// added, removed and modified rows. Columns are matched by name, so their order does not matter.
+pub fn process_csv_diff(opts: &CsvDiffOpts) -> Result<DiffSummary> {
+    let rejects = Rejects::open(&opts.reader)?;
+    let old = load(&opts.old, &opts.reader, &opts.types, rejects.as_ref())?;
+    let new = load(&opts.new, &opts.reader, &opts.types, rejects.as_ref())?;
+    let (changes, summary) = diff(&old, &new, &opts.key)?;
+
+    let mut writer = get_writer(&opts.output)?;
+    match opts.format {
+        Some(format) => {
+            let mut writer = create_row_writer(format, writer, &[]);
+            for change in &changes {
+                writer.write_row(&change.to_map())?;
+            }
+            writer.finish()?;
+        }
+        None => {
+            for column in &summary.removed_columns {
+                writeln!(writer, "- column {:?}", column)?;
+            }
+            for column in &summary.added_columns {
+                writeln!(writer, "+ column {:?}", column)?;
+            }
+            for change in &changes {
+                write!(writer, "{}", change)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(summary)
+}
+
+fn load(
+    input: &str,
+    reader: &CsvReaderOpts,
+    types: &CsvTypeOpts,
+    rejects: Option<&Rejects>,
+) -> Result<Loaded> {
+    let mut source = CsvSource::open_with_rejects(input, reader, rejects)?;
+    let headers = source.headers().to_vec();
+    let sample = source
+        .by_ref()
+        .take(types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    // --types and --date apply to the columns of both files, a file may not have all of them
+    let opts = file_types(&headers, types);
+    let types = infer_types(&headers, &sample, &opts)?;
+    let mut converter = TypeConverter::from_opts(&headers, types.clone(), &opts);
+    let rows = sample
+        .into_iter()
+        .map(Ok)
+        .chain(source)
+        .map(|record| {
+            let record = record?;
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            Ok((line, converter.convert(&headers, &record)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Loaded {
+        headers,
+        types,
+        rows,
+    })
+}
+
+fn diff(old: &Loaded, new: &Loaded, key: &[String]) -> Result<(Vec<RowChange>, DiffSummary)> {
+    let position = |loaded: &Loaded, name: &str, file: &str| {
+        loaded
+            .headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow!("key column {:?} is missing in the {} file", name, file))
+    };
+    let old_key = key
+        .iter()
+        .map(|k| position(old, k, "old"))
+        .collect::<Result<Vec<_>>>()?;
+    let new_key = key
+        .iter()
+        .map(|k| position(new, k, "new"))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut summary = DiffSummary {
+        added_columns: new
+            .headers
+            .iter()
+            .filter(|h| !old.headers.contains(h))
+            .cloned()
+            .collect(),
+        removed_columns: old
+            .headers
+            .iter()
+            .filter(|h| !new.headers.contains(h))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    // the non-key columns of both files, as (name, old index, new index)
+    let common = old
+        .headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !key.contains(h))
+        .filter_map(|(i, h)| Some((h, i, new.headers.iter().position(|n| n == h)?)))
+        .collect::<Vec<_>>();
+
+    // a key column that is a number in one file and a string in the other is keyed by its text
+    let text = old_key
+        .iter()
+        .zip(&new_key)
+        .map(|(o, n)| keys_as_text(old.types[*o], new.types[*n]))
+        .collect::<Vec<_>>();
+    let row_key = |row: &[Value], idxs: &[usize]| {
+        idxs.iter()
+            .zip(&text)
+            .map(|(i, text)| {
+                if *text {
+                    text_key(&value_text(&row[*i]))
+                } else {
+                    value_key(&row[*i])
+                }
+            })
+            .collect()
+    };
+    let mut index: HashMap<Vec<String>, usize> = HashMap::with_capacity(new.rows.len());
+    for (i, (line, row)) in new.rows.iter().enumerate() {
+        if index.insert(row_key(row, &new_key), i).is_some() {
+            bail!("duplicate key at line {} of the new file", line);
+        }
+    }
+
+    let cells = |loaded: &Loaded, row: &[Value], idxs: &[usize], keep: bool| {
+        loaded
+            .headers
+            .iter()
+            .zip(row)
+            .enumerate()
+            .filter(|(i, _)| idxs.contains(i) == keep)
+            .map(|(_, (h, v))| (h.clone(), v.clone()))
+            .collect::<Vec<_>>()
+    };
+    let mut changes = Vec::new();
+    let mut seen = HashSet::with_capacity(old.rows.len());
+    let mut matched = vec![false; new.rows.len()];
+    for (line, row) in &old.rows {
+        let k: Vec<String> = row_key(row, &old_key);
+        if !seen.insert(k.clone()) {
+            bail!("duplicate key at line {} of the old file", line);
+        }
+        let key = cells(old, row, &old_key, true);
+        let Some(&i) = index.get(&k) else {
+            summary.removed += 1;
+            changes.push(RowChange {
+                kind: ChangeKind::Removed,
+                key,
+                row: cells(old, row, &old_key, false),
+                cells: Vec::new(),
+            });
+            continue;
+        };
+        matched[i] = true;
+        let after = &new.rows[i].1;
+        let modified = common
+            .iter()
+            .filter(|(_, a, b)| !same(&row[*a], &after[*b]))
+            .map(|(h, a, b)| (h.to_string(), row[*a].clone(), after[*b].clone()))
+            .collect::<Vec<_>>();
+        if modified.is_empty() {
+            summary.unchanged += 1;
+        } else {
+            summary.modified += 1;
+            changes.push(RowChange {
+                kind: ChangeKind::Modified,
+                key,
+                row: Vec::new(),
+                cells: modified,
+            });
+        }
+    }
+    for ((_, row), _) in new.rows.iter().zip(matched).filter(|(_, m)| !m) {
+        summary.added += 1;
+        changes.push(RowChange {
+            kind: ChangeKind::Added,
+            key: cells(new, row, &new_key, true),
+            row: cells(new, row, &new_key, false),
+            cells: Vec::new(),
+        });
+    }
+    Ok((changes, summary))
+}
+
+/// numbers are the same if they are equal, so `17` and `17.0` are not a change. A string is the
+/// same as another cell only if their text is equal: `17` and `"17"` from a file where the column
+/// was inferred as a string are not a change, but `"007"` and `7` are.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Null, Value::Null) => true,
+        (Value::Null, _) | (_, Value::Null) => false,
+        (Value::Number(_), Value::Number(_)) => compare_values(a, b) == Some(Ordering::Equal),
+        _ => value_text(a) == value_text(b),
+    }
+}
+
+impl RowChange {
+    /// the change as a record for the output formats
+    fn to_map(&self) -> Map<String, Value> {
+        let object = |cells: &[(String, Value)]| Value::Object(cells.iter().cloned().collect());
+        let mut ret = Map::new();
+        ret.insert("change".into(), Value::from(self.kind.to_string()));
+        ret.insert("key".into(), object(&self.key));
+        match self.kind {
+            ChangeKind::Added | ChangeKind::Removed => {
+                ret.insert("row".into(), object(&self.row));
+            }
+            ChangeKind::Modified => {
+                let cells = self
+                    .cells
+                    .iter()
+                    .map(|(column, before, after)| {
+                        (column.clone(), json!({"before": before, "after": after}))
+                    })
+                    .collect();
+                ret.insert("cells".into(), Value::Object(cells));
+            }
+        }
+        ret
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// the readable report of a change, e.g.
+///
+/// ```text
+/// ~ Name="Dennis Harris"
+///     Kit Number: 17 -> 18
+/// ```
+impl fmt::Display for RowChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells = |cells: &[(String, Value)]| {
+            cells
+                .iter()
+                .map(|(column, value)| format!("{}={}", column, value))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let sign = match self.kind {
+            ChangeKind::Added => "+",
+            ChangeKind::Removed => "-",
+            ChangeKind::Modified => "~",
+        };
+        write!(f, "{} {}", sign, cells(&self.key))?;
+        if !self.row.is_empty() {
+            write!(f, ": {}", cells(&self.row))?;
+        }
+        writeln!(f)?;
+        for (column, before, after) in &self.cells {
+            writeln!(f, "    {}: {} -> {}", column, before, after)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} modified, {} unchanged row(s)",
+            self.added, self.removed, self.modified, self.unchanged
+        )?;
+        if !self.added_columns.is_empty() || !self.removed_columns.is_empty() {
+            write!(
+                f,
+                ", {} added, {} removed column(s)",
+                self.added_columns.len(),
+                self.removed_columns.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(headers: &[&str], rows: Value) -> Loaded {
+        let rows = rows
+            .as_array()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (i as u64 + 2, row.as_array().unwrap().clone()))
+            .collect::<Vec<_>>();
+        // the type of the first value of every column stands in for the inferred one
+        let types = (0..headers.len())
+            .map(
+                |j| match rows.iter().map(|(_, r)| &r[j]).find(|v| !v.is_null()) {
+                    Some(Value::Number(n)) if n.is_f64() => ColumnType::Float,
+                    Some(Value::Number(_)) => ColumnType::Int,
+                    Some(Value::Bool(_)) => ColumnType::Bool,
+                    Some(_) => ColumnType::String,
+                    None => ColumnType::Null,
+                },
+            )
+            .collect();
+        Loaded {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            types,
+            rows,
+        }
+    }
+
+    #[test]
+    fn test_diff_rows() {
+        let old = loaded(
+            &["Name", "DOB", "Kit Number", "Team"],
+            json!([
+                ["Dennis Harris", "2055-06-26", 17, "A"],
+                ["Glenn Keller", "2035-12-12", 92, "B"],
+                ["Rhoda Lane", "2001-01-01", 3, "C"]
+            ]),
+        );
+        // the columns are reordered, Team is dropped and Kit Number is a string in this file
+        let new = loaded(
+            &["Kit Number", "Name", "DOB", "Nationality"],
+            json!([
+                ["18", "Dennis Harris", "2055-06-26", "AR"],
+                ["3", "Rhoda Lane", "2001-01-01", "GA"],
+                ["7", "Eva Moss", null, "FL"]
+            ]),
+        );
+        let (changes, summary) = diff(&old, &new, &["Name".to_string()]).unwrap();
+        assert_eq!(
+            (
+                summary.added,
+                summary.removed,
+                summary.modified,
+                summary.unchanged
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(summary.added_columns, ["Nationality"]);
+        assert_eq!(summary.removed_columns, ["Team"]);
+
+        assert_eq!(
+            changes[0].to_string(),
+            "~ Name=\"Dennis Harris\"\n    Kit Number: 17 -> \"18\"\n"
+        );
+        assert_eq!(changes[1].kind, ChangeKind::Removed);
+        assert_eq!(
+            Value::Object(changes[2].to_map()),
+            json!({
+                "change": "added",
+                "key": {"Name": "Eva Moss"},
+                "row": {"Kit Number": "7", "DOB": null, "Nationality": "FL"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff_exact_keys_and_text() {
+        let old = loaded(
+            &["Id", "Code"],
+            json!([
+                [1234567890123456789_i64, "007"],
+                [1234567890123456788_i64, "9"]
+            ]),
+        );
+        let new = loaded(
+            &["Id", "Code"],
+            json!([[1234567890123456789_i64, 7], [1234567890123456788_i64, 9]]),
+        );
+        let (changes, summary) = diff(&old, &new, &["Id".to_string()]).unwrap();
+        assert_eq!((summary.modified, summary.unchanged), (1, 1));
+        assert_eq!(
+            changes[0].to_string(),
+            "~ Id=1234567890123456789\n    Code: \"007\" -> 7\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_mismatched_key_types() {
+        // the old Id is inferred as an int, the new one as a string
+        let old = loaded(&["Id", "Name"], json!([[1, "Dennis"], [2, "Glenn"]]));
+        let new = loaded(
+            &["Id", "Name"],
+            json!([["1", "Dennis"], ["2", "Glenn"], ["X3", "Eva"]]),
+        );
+        let (_, summary) = diff(&old, &new, &["Id".to_string()]).unwrap();
+        assert_eq!(
+            (
+                summary.added,
+                summary.removed,
+                summary.modified,
+                summary.unchanged
+            ),
+            (1, 0, 0, 2)
+        );
+    }
+
+    #[test]
+    fn test_diff_errors() {
+        let old = loaded(&["Name"], json!([["a"], ["a"]]));
+        let new = loaded(&["Name"], json!([["a"]]));
+        let err = diff(&old, &new, &["Name".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "duplicate key at line 3 of the old file");
+        let err = diff(&old, &new, &["DOB".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "key column \"DOB\" is missing in the old file"
+        );
+    }
+}
@@ -0,0 +1,861 @@
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::{cmp::Ordering, fmt};
+
+use super::csv_types::{compare_values, parse_float, value_text};
+
+/// words that end a multi-word column name like `Kit Number`, a column whose name is one of them
+/// has to be quoted with backticks or brackets, e.g. `` `Order` `` or `[Order]`
+const KEYWORDS: &[&str] = &[
+    "and", "or", "not", "is", "null", "true", "false", "contains", "matches", "like", "in",
+    "select", "from", "where", "group", "by", "order", "limit", "offset", "asc", "desc", "join",
+    "inner", "left", "right", "outer", "full", "on", "as", "having", "distinct",
+];
+
+/// symbols, longest first so `<=` is not read as `<` followed by `=`
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<>", "<=", ">=", "&&", "||", "=~", "=", "<", ">", "!", "(", ")", ",", "+", "-",
+    "*", "/", ".",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// a bare word, either a keyword or (part of) a column name
+    Ident(String),
+    /// a column name quoted with backticks or brackets
+    QuotedIdent(String),
+    /// a string literal in double or single quotes
+    Str(String),
+    Number(Value),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub table: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Expr is a parsed expression, column references are bound to row indices with [`Expr::bind`]
+/// before the expression is evaluated against rows of typed values.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(Value),
+    Column(ColumnRef),
+    Index(usize),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    Arith(ArithOp, Box<Expr>, Box<Expr>),
+    /// `x is null`, or `x is not null` when negated
+    IsNull(Box<Expr>, bool),
+    In(Box<Expr>, Vec<Expr>, bool),
+    Contains(Box<Expr>, Box<Expr>, bool),
+    /// `x matches "regex"`, `x =~ "regex"` and `x like "pattern%"`
+    Matches(Box<Expr>, Regex, bool),
+    /// `COUNT(*)`, `SUM(x)`, `COUNT(DISTINCT x)`, ..., only valid in queries
+    Aggregate(AggFunc, Option<Box<Expr>>, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// ExprParser is a recursive descent parser over the tokens of an expression,
+/// the token helpers are public so the SQL parser can drive it for the clauses around expressions.
+pub struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// Filter is a `--where` expression bound to the headers of the input.
+pub struct Filter {
+    expr: Expr,
+    columns: Vec<usize>,
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => s.push('\n'),
+                        Some((_, 't')) => s.push('\t'),
+                        Some((_, other)) => s.push(other),
+                        None => bail!("unterminated string starting at {}", start),
+                    },
+                    Some((_, ch)) if ch == c => break,
+                    Some((_, ch)) => s.push(ch),
+                    None => bail!("unterminated string starting at {}", start),
+                }
+            }
+            tokens.push(Token::Str(s));
+        } else if c == '`' || c == '[' {
+            let end = if c == '`' { '`' } else { ']' };
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, ch)) if ch == end => break,
+                    Some((_, ch)) => s.push(ch),
+                    None => bail!("unterminated column name starting at {}", start),
+                }
+            }
+            tokens.push(Token::QuotedIdent(s));
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            let mut is_float = false;
+            while let Some(&(i, ch)) = chars.peek() {
+                let exponent_sign = (ch == '-' || ch == '+')
+                    && matches!(input[..i].chars().last(), Some('e' | 'E'));
+                if ch.is_ascii_digit() || ch == '.' || ch == 'e' || ch == 'E' || exponent_sign {
+                    is_float |= !ch.is_ascii_digit();
+                    end = i + ch.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let text = &input[start..end];
+            let number = if is_float {
+                text.parse::<f64>().ok().map(Value::from)
+            } else {
+                text.parse::<i64>().ok().map(Value::from)
+            };
+            tokens.push(Token::Number(
+                number.ok_or_else(|| anyhow!("invalid number {:?}", text))?,
+            ));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, ch)) = chars.peek() {
+                if ch.is_alphanumeric() || ch == '_' {
+                    end = i + ch.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Ident(input[start..end].to_string()));
+        } else {
+            let rest = &input[start..];
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .ok_or_else(|| anyhow!("unexpected character {:?} at {}", c, start))?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+impl ExprParser {
+    pub fn new(input: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(input)?,
+            pos: 0,
+        })
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// look `n` tokens ahead of the current one
+    pub fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n)
+    }
+
+    pub fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    pub fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            bail!("expected {} {}", keyword.to_uppercase(), self.found());
+        }
+        Ok(())
+    }
+
+    pub fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    pub fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if !self.eat_symbol(symbol) {
+            bail!("expected `{}` {}", symbol, self.found());
+        }
+        Ok(())
+    }
+
+    /// describes the current token for error messages
+    pub fn found(&self) -> String {
+        match self.peek() {
+            Some(token) => format!("but found {}", token),
+            None => "but the expression ended".to_string(),
+        }
+    }
+
+    /// parse a column name, a run of bare words (`Kit Number`) or a quoted name
+    pub fn parse_identifier(&mut self) -> Result<String> {
+        match self.peek().cloned() {
+            Some(Token::QuotedIdent(name)) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            Some(Token::Ident(word)) if !is_keyword(&word) => {
+                self.pos += 1;
+                let mut words = vec![word];
+                while let Some(Token::Ident(word)) = self.peek() {
+                    if is_keyword(word) {
+                        break;
+                    }
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                Ok(words.join(" "))
+            }
+            _ => bail!("expected a column name {}", self.found()),
+        }
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_symbol("||") || self.eat_keyword("or") {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_not()?;
+        while self.eat_symbol("&&") || self.eat_keyword("and") {
+            let rhs = self.parse_not()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat_symbol("!") || self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_predicate()
+    }
+
+    fn parse_predicate(&mut self) -> Result<Expr> {
+        let lhs = self.parse_additive()?;
+
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(Expr::IsNull(Box::new(lhs), negated));
+        }
+        let negated = self.eat_keyword("not");
+        if self.eat_keyword("in") {
+            self.expect_symbol("(")?;
+            let mut items = vec![self.parse_expr()?];
+            while self.eat_symbol(",") {
+                items.push(self.parse_expr()?);
+            }
+            self.expect_symbol(")")?;
+            return Ok(Expr::In(Box::new(lhs), items, negated));
+        }
+        if self.eat_keyword("contains") {
+            let rhs = self.parse_additive()?;
+            return Ok(Expr::Contains(Box::new(lhs), Box::new(rhs), negated));
+        }
+        if self.eat_keyword("matches") || self.eat_symbol("=~") {
+            let regex = Regex::new(&self.parse_pattern()?)?;
+            return Ok(Expr::Matches(Box::new(lhs), regex, negated));
+        }
+        if self.eat_keyword("like") {
+            let regex = like_to_regex(&self.parse_pattern()?)?;
+            return Ok(Expr::Matches(Box::new(lhs), regex, negated));
+        }
+        if negated {
+            bail!(
+                "expected IN, CONTAINS, MATCHES or LIKE after NOT {}",
+                self.found()
+            );
+        }
+
+        let op = match self.peek() {
+            Some(Token::Symbol("==" | "=")) => CmpOp::Eq,
+            Some(Token::Symbol("!=" | "<>")) => CmpOp::Ne,
+            Some(Token::Symbol("<")) => CmpOp::Lt,
+            Some(Token::Symbol("<=")) => CmpOp::Le,
+            Some(Token::Symbol(">")) => CmpOp::Gt,
+            Some(Token::Symbol(">=")) => CmpOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.parse_additive()?;
+        // `x == null` reads better than `x is null` for people used to other languages
+        let ret = match (op, &lhs, &rhs) {
+            (CmpOp::Eq | CmpOp::Ne, e, Expr::Literal(Value::Null))
+            | (CmpOp::Eq | CmpOp::Ne, Expr::Literal(Value::Null), e) => {
+                Expr::IsNull(Box::new(e.clone()), op == CmpOp::Ne)
+            }
+            _ => Expr::Compare(op, Box::new(lhs), Box::new(rhs)),
+        };
+        Ok(ret)
+    }
+
+    fn parse_pattern(&mut self) -> Result<String> {
+        match self.next_token() {
+            Some(Token::Str(s)) => Ok(s),
+            _ => bail!("expected a string pattern"),
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                ArithOp::Add
+            } else if self.eat_symbol("-") {
+                ArithOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_multiplicative()?;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                ArithOp::Mul
+            } else if self.eat_symbol("/") {
+                ArithOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.eat_symbol("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let expr = match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Expr::Literal(n)
+            }
+            Some(Token::Str(s)) => {
+                self.pos += 1;
+                Expr::Literal(Value::String(s))
+            }
+            Some(Token::Symbol("(")) => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                expr
+            }
+            Some(Token::Ident(w)) if w.eq_ignore_ascii_case("null") => {
+                self.pos += 1;
+                Expr::Literal(Value::Null)
+            }
+            Some(Token::Ident(w)) if w.eq_ignore_ascii_case("true") => {
+                self.pos += 1;
+                Expr::Literal(Value::Bool(true))
+            }
+            Some(Token::Ident(w)) if w.eq_ignore_ascii_case("false") => {
+                self.pos += 1;
+                Expr::Literal(Value::Bool(false))
+            }
+            Some(Token::Ident(w)) if matches!(self.peek_nth(1), Some(Token::Symbol("("))) => {
+                let func = AggFunc::from_name(&w)
+                    .ok_or_else(|| anyhow!("unknown function {}", w.to_uppercase()))?;
+                self.pos += 2;
+                let distinct = self.eat_keyword("distinct");
+                let arg = if func == AggFunc::Count && !distinct && self.eat_symbol("*") {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expr()?))
+                };
+                self.expect_symbol(")")?;
+                Expr::Aggregate(func, arg, distinct)
+            }
+            _ => Expr::Column(self.parse_column()?),
+        };
+        Ok(expr)
+    }
+
+    /// parse `column` or `table.column`
+    fn parse_column(&mut self) -> Result<ColumnRef> {
+        let name = self.parse_identifier()?;
+        if self.eat_symbol(".") {
+            return Ok(ColumnRef {
+                table: Some(name),
+                name: self.parse_identifier()?,
+            });
+        }
+        Ok(ColumnRef { table: None, name })
+    }
+}
+
+/// translate a SQL LIKE pattern to a regex, `%` matches any run of characters and `_` one character
+fn like_to_regex(pattern: &str) -> Result<Regex> {
+    let mut re = String::from("(?s)^");
+    for c in pattern.chars() {
+        match c {
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(Regex::new(&re)?)
+}
+
+/// truthiness of a value, so `--where Active` works on a bool column
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+pub fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => parse_float(s),
+        _ => None,
+    }
+}
+
+impl Expr {
+    /// replace the column references with row indices, `resolve` reports unknown columns
+    pub fn bind(&mut self, resolve: &dyn Fn(&ColumnRef) -> Result<usize>) -> Result<()> {
+        if let Expr::Column(column) = self {
+            *self = Expr::Index(resolve(column)?);
+            return Ok(());
+        }
+        for child in self.children_mut() {
+            child.bind(resolve)?;
+        }
+        Ok(())
+    }
+
+    pub fn has_aggregate(&self) -> bool {
+        matches!(self, Expr::Aggregate(..)) || self.children().iter().any(|e| e.has_aggregate())
+    }
+
+    /// the row indices the (bound) expression reads
+    pub fn columns(&self, ret: &mut Vec<usize>) {
+        if let Expr::Index(idx) = self {
+            if !ret.contains(idx) {
+                ret.push(*idx);
+            }
+        }
+        for child in self.children() {
+            child.columns(ret);
+        }
+    }
+
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Index(_) => vec![],
+            Expr::Not(e) | Expr::Neg(e) | Expr::IsNull(e, _) | Expr::Matches(e, _, _) => vec![e],
+            Expr::And(a, b)
+            | Expr::Or(a, b)
+            | Expr::Compare(_, a, b)
+            | Expr::Arith(_, a, b)
+            | Expr::Contains(a, b, _) => vec![a, b],
+            Expr::In(e, items, _) => std::iter::once(&**e).chain(items).collect(),
+            Expr::Aggregate(_, arg, _) => arg.iter().map(|e| &**e).collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Index(_) => vec![],
+            Expr::Not(e) | Expr::Neg(e) | Expr::IsNull(e, _) | Expr::Matches(e, _, _) => vec![e],
+            Expr::And(a, b)
+            | Expr::Or(a, b)
+            | Expr::Compare(_, a, b)
+            | Expr::Arith(_, a, b)
+            | Expr::Contains(a, b, _) => vec![a, b],
+            Expr::In(e, items, _) => std::iter::once(&mut **e).chain(items).collect(),
+            Expr::Aggregate(_, arg, _) => arg.iter_mut().map(|e| &mut **e).collect(),
+        }
+    }
+
+    /// evaluate the expression against a row of typed values, comparisons use the same coercion
+    /// as type inference, and any comparison with null is false
+    pub fn eval(&self, row: &[Value]) -> Value {
+        match self {
+            Expr::Literal(v) => v.clone(),
+            Expr::Index(idx) => row.get(*idx).cloned().unwrap_or(Value::Null),
+            // unbound columns never match anything, and aggregates are computed by the query
+            Expr::Column(_) | Expr::Aggregate(..) => Value::Null,
+            Expr::Not(e) => Value::Bool(!is_truthy(&e.eval(row))),
+            Expr::Neg(e) => {
+                let v = e.eval(row);
+                match v.as_i64().and_then(i64::checked_neg) {
+                    Some(n) => Value::from(n),
+                    // `-i64::MIN` and a u64 above i64::MAX do not fit in an i64
+                    None => as_number(&v).map_or(Value::Null, |f| Value::from(-f)),
+                }
+            }
+            Expr::And(a, b) => Value::Bool(is_truthy(&a.eval(row)) && is_truthy(&b.eval(row))),
+            Expr::Or(a, b) => Value::Bool(is_truthy(&a.eval(row)) || is_truthy(&b.eval(row))),
+            Expr::Compare(op, a, b) => {
+                let ord = compare_values(&a.eval(row), &b.eval(row));
+                Value::Bool(ord.is_some_and(|ord| match op {
+                    CmpOp::Eq => ord == Ordering::Equal,
+                    CmpOp::Ne => ord != Ordering::Equal,
+                    CmpOp::Lt => ord == Ordering::Less,
+                    CmpOp::Le => ord != Ordering::Greater,
+                    CmpOp::Gt => ord == Ordering::Greater,
+                    CmpOp::Ge => ord != Ordering::Less,
+                }))
+            }
+            Expr::Arith(op, a, b) => arith(*op, &a.eval(row), &b.eval(row)),
+            Expr::IsNull(e, negated) => Value::Bool(e.eval(row).is_null() != *negated),
+            Expr::In(e, items, negated) => {
+                let value = e.eval(row);
+                if value.is_null() {
+                    return Value::Bool(false);
+                }
+                let found = items
+                    .iter()
+                    .any(|item| compare_values(&value, &item.eval(row)) == Some(Ordering::Equal));
+                Value::Bool(found != *negated)
+            }
+            Expr::Contains(a, b, negated) => match (a.eval(row), b.eval(row)) {
+                (Value::Null, _) | (_, Value::Null) => Value::Bool(false),
+                (a, b) => Value::Bool(value_text(&a).contains(&value_text(&b)) != *negated),
+            },
+            Expr::Matches(e, regex, negated) => match e.eval(row) {
+                Value::Null => Value::Bool(false),
+                v => Value::Bool(regex.is_match(&value_text(&v)) != *negated),
+            },
+        }
+    }
+}
+
+pub fn arith(op: ArithOp, a: &Value, b: &Value) -> Value {
+    if let (Some(x), Some(y), true) = (a.as_i64(), b.as_i64(), op != ArithOp::Div) {
+        let ret = match op {
+            ArithOp::Add => x.checked_add(y),
+            ArithOp::Sub => x.checked_sub(y),
+            _ => x.checked_mul(y),
+        };
+        if let Some(ret) = ret {
+            return Value::from(ret);
+        }
+    }
+    let (Some(x), Some(y)) = (as_number(a), as_number(b)) else {
+        return Value::Null;
+    };
+    let ret = match op {
+        ArithOp::Add => x + y,
+        ArithOp::Sub => x - y,
+        ArithOp::Mul => x * y,
+        ArithOp::Div if y == 0.0 => return Value::Null,
+        ArithOp::Div => x / y,
+    };
+    Value::from(ret)
+}
+
+impl Filter {
+    /// parse the `--where` expression and bind it to the headers
+    pub fn new(source: &str, headers: &[String]) -> Result<Self> {
+        let mut parser = ExprParser::new(source)?;
+        let mut expr = parser.parse_expr()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {} in --where", token);
+        }
+        if expr.has_aggregate() {
+            bail!("aggregates like COUNT(*) are only allowed in `rcli csv query`");
+        }
+        expr.bind(&|column| {
+            if column.table.is_some() {
+                bail!("--where columns cannot be qualified with a table name");
+            }
+            headers
+                .iter()
+                .position(|h| h == &column.name)
+                .ok_or_else(|| anyhow!("unknown column {:?} in --where", column.name))
+        })?;
+        let mut columns = Vec::new();
+        expr.columns(&mut columns);
+        Ok(Self { expr, columns })
+    }
+
+    /// indices of the columns the filter reads, only these have to be converted before `matches`
+    pub fn columns(&self) -> &[usize] {
+        &self.columns
+    }
+
+    pub fn matches(&self, row: &[Value]) -> bool {
+        is_truthy(&self.expr.eval(row))
+    }
+}
+
+impl AggFunc {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "count" => Some(AggFunc::Count),
+            "sum" => Some(AggFunc::Sum),
+            "avg" => Some(AggFunc::Avg),
+            "min" => Some(AggFunc::Min),
+            "max" => Some(AggFunc::Max),
+            _ => None,
+        }
+    }
+}
+
+impl From<AggFunc> for &'static str {
+    fn from(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => "COUNT",
+            AggFunc::Sum => "SUM",
+            AggFunc::Avg => "AVG",
+            AggFunc::Min => "MIN",
+            AggFunc::Max => "MAX",
+        }
+    }
+}
+
+impl From<CmpOp> for &'static str {
+    fn from(op: CmpOp) -> Self {
+        match op {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+impl From<ArithOp> for &'static str {
+    fn from(op: ArithOp) -> Self {
+        match op {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+        }
+    }
+}
+
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.table {
+            Some(table) => write!(f, "{}.{}", table, self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Display writes the expression back as text, queries use it to name computed columns
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let not = |negated: &bool| if *negated { "NOT " } else { "" };
+        match self {
+            Expr::Literal(Value::String(s)) => write!(f, "{:?}", s),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Column(column) => write!(f, "{}", column),
+            Expr::Index(idx) => write!(f, "#{}", idx),
+            Expr::Not(e) => write!(f, "NOT {}", e),
+            Expr::Neg(e) => write!(f, "-{}", e),
+            Expr::And(a, b) => write!(f, "({} AND {})", a, b),
+            Expr::Or(a, b) => write!(f, "({} OR {})", a, b),
+            Expr::Compare(op, a, b) => write!(f, "{} {} {}", a, Into::<&str>::into(*op), b),
+            Expr::Arith(op, a, b) => write!(f, "{} {} {}", a, Into::<&str>::into(*op), b),
+            Expr::IsNull(e, negated) => write!(f, "{} IS {}NULL", e, not(negated)),
+            Expr::In(e, items, negated) => {
+                let items = items.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+                write!(f, "{} {}IN ({})", e, not(negated), items.join(", "))
+            }
+            Expr::Contains(a, b, negated) => write!(f, "{} {}CONTAINS {}", a, not(negated), b),
+            Expr::Matches(e, re, negated) => {
+                write!(f, "{} {}MATCHES {:?}", e, not(negated), re.as_str())
+            }
+            Expr::Aggregate(func, arg, distinct) => {
+                let func = Into::<&str>::into(*func);
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                match arg {
+                    Some(arg) => write!(f, "{}({}{})", func, distinct, arg),
+                    None => write!(f, "{}(*)", func),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(w) => write!(f, "`{}`", w),
+            Token::QuotedIdent(w) => write!(f, "`{}`", w),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn headers() -> Vec<String> {
+        ["Name", "Nationality", "Kit Number", "Active", "Note"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn matches(source: &str, row: Value) -> bool {
+        let filter = Filter::new(source, &headers()).unwrap();
+        let row = row.as_array().unwrap().clone();
+        filter.matches(&row)
+    }
+
+    #[test]
+    fn test_multi_word_columns_and_logic() {
+        let row = json!(["Dennis Harris", "AR", 17, true, null]);
+        assert!(matches(
+            r#"Nationality == "AR" && Kit Number > 10"#,
+            row.clone()
+        ));
+        assert!(!matches(
+            r#"Nationality == "AR" and Kit Number > 20"#,
+            row.clone()
+        ));
+        assert!(matches(
+            r#"Nationality = 'GA' or `Kit Number` >= 17"#,
+            row.clone()
+        ));
+        assert!(matches(r#"!(Kit Number < 10) && Active"#, row.clone()));
+        assert!(matches("Kit Number * 2 - 4 == 30", row));
+
+        // negating integers that overflow i64 falls back to floats
+        assert!(matches(
+            "-Kit Number == -17",
+            json!(["a", "AR", 17, true, null])
+        ));
+        let row = json!(["a", "AR", i64::MIN, true, null]);
+        assert!(matches("-Kit Number == 9223372036854775808.0", row));
+        let row = json!(["a", "AR", u64::MAX, true, null]);
+        assert!(matches("-Kit Number < -9223372036854775807", row));
+    }
+
+    #[test]
+    fn test_string_predicates_and_nulls() {
+        let row = json!(["Dennis Harris", "AR", 17, true, null]);
+        assert!(matches(r#"Name contains "Harr""#, row.clone()));
+        assert!(matches(r#"Name matches "^D\\w+ H""#, row.clone()));
+        assert!(matches(
+            r#"Name =~ "ris$" && Name like "D%s H_rr%""#,
+            row.clone()
+        ));
+        assert!(matches(r#"Nationality not in ("GA", "FL")"#, row.clone()));
+        assert!(matches("Note is null && Name is not null", row.clone()));
+        assert!(matches("Note == null", row.clone()));
+        // comparisons with null are false both ways
+        assert!(!matches("Note > 1 || Note <= 1", row));
+    }
+
+    #[test]
+    fn test_coercion_follows_inference() {
+        // a string column holding numbers (e.g. with leading zeros) still compares numerically
+        let row = json!(["a", "AR", "017", true, null]);
+        assert!(matches("Kit Number > 9", row.clone()));
+        assert!(matches(r#"Kit Number == "017""#, row));
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        assert!(Filter::new("Kit > 1", &headers()).is_err());
+        assert!(Filter::new("Name ==", &headers()).is_err());
+        assert!(Filter::new(r#"Name == "a" )"#, &headers()).is_err());
+        assert!(Filter::new(r#"Name matches "(""#, &headers()).is_err());
+    }
+}
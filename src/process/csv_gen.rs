@@ -0,0 +1,334 @@
+use anyhow::{bail, Context, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Days, NaiveDate,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{fmt::Write, fs};
+
+use super::csv_writer::{create_csv_writer, create_row_writer};
+use crate::{get_writer, CsvGenOpts};
+
+/// the longest repetition generated for `*`, `+` and `{n,}` in a regex
+const MAX_REPEAT: u32 = 8;
+
+const FIRST_NAMES: &[&str] = &[
+    "Alex", "Alice", "Amelia", "Ana", "Ben", "Carlos", "Chloe", "Daniel", "Dennis", "Elena",
+    "Emma", "Ethan", "Fatima", "Glenn", "Grace", "Hannah", "Hugo", "Isabel", "Ivan", "Jack",
+    "Jennie", "Julia", "Kenji", "Leo", "Liam", "Lucia", "Marco", "Maria", "Mateo", "Mia", "Noah",
+    "Nora", "Olivia", "Omar", "Paul", "Priya", "Rosa", "Sam", "Sofia", "Theo", "Victor", "Yuki",
+    "Zoe",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Adams", "Bauer", "Brown", "Chen", "Costa", "Diaz", "Dubois", "Evans", "Fischer", "Garcia",
+    "Harris", "Ito", "Jensen", "Keller", "Khan", "Kim", "Lopez", "Martin", "Meyer", "Moreau",
+    "Nash", "Nguyen", "Novak", "Okafor", "Perez", "Rossi", "Santos", "Schmidt", "Silva", "Smith",
+    "Tanaka", "Taylor", "Walker", "Weber", "Wong", "Young",
+];
+
+/// ISO 3166-1 alpha-2 country codes
+const COUNTRIES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+/// Spec is the YAML document given to `rcli csv gen --spec`, e.g.
+///
+/// ```yaml
+/// columns:
+///   - name: Name
+///     gen: name
+///   - name: Position
+///     gen: regex
+///     pattern: "[A-Z][a-z]{5,8}"
+///   - name: DOB
+///     gen: date
+///     min: 1920-01-01
+///     max: 2060-12-31
+///     format: "%m/%d/%Y"
+///   - name: Nationality
+///     gen: country
+///   - name: Kit Number
+///     gen: int
+///     min: 1
+///     max: 99
+///   - name: Foot
+///     gen: enum
+///     enum: [left, right]
+///     nulls: 0.1
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    pub columns: Vec<ColumnSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub gen: GenKind,
+    /// the regex the strings of a `regex` column match
+    pub pattern: Option<String>,
+    /// the values an `enum` column picks from
+    #[serde(rename = "enum")]
+    pub values: Option<Vec<Value>>,
+    /// inclusive bounds of `int` and `date` columns
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// strftime format of a `date` column, ISO 8601 if not given
+    pub format: Option<String>,
+    /// the share of empty cells, from 0 to 1
+    #[serde(default)]
+    pub nulls: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GenKind {
+    /// a first and last name
+    Name,
+    /// an ISO 3166-1 alpha-2 country code
+    Country,
+    Int,
+    Date,
+    Regex,
+    Enum,
+}
+
+/// the generator of a column, with the regex compiled and the bounds resolved
+#[derive(Debug)]
+enum Generator {
+    Name,
+    Country,
+    Int(i64, i64),
+    Date {
+        min: NaiveDate,
+        days: u64,
+        format: String,
+    },
+    Regex(rand_regex::Regex),
+    Enum(Vec<Value>),
+}
+
+/// process_csv_gen writes `--rows` rows of fake data generated from the spec, the same spec and
+/// `--seed` always give the same rows. It returns the number of rows written.
+pub fn process_csv_gen(opts: &CsvGenOpts) -> Result<u64> {
+    let spec = Spec::load(&opts.spec)?;
+    let headers = spec
+        .columns
+        .iter()
+        .map(|c| c.name.clone())
+        .collect::<Vec<_>>();
+    let generators = spec
+        .columns
+        .iter()
+        .map(|column| Generator::new(column).with_context(|| format!("column {:?}", column.name)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut rng = match opts.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let writer = get_writer(&opts.output)?;
+    let mut writer = match opts.format {
+        Some(format) => create_row_writer(format, writer, &headers),
+        None => create_csv_writer(writer, b',', &headers)?,
+    };
+    for _ in 0..opts.rows {
+        let row = generate_row(&spec.columns, &generators, &mut rng);
+        writer.write_row(&headers.iter().cloned().zip(row).collect::<Map<_, _>>())?;
+    }
+    writer.finish()?;
+    Ok(opts.rows)
+}
+
+fn generate_row(columns: &[ColumnSpec], generators: &[Generator], rng: &mut StdRng) -> Vec<Value> {
+    columns
+        .iter()
+        .zip(generators)
+        .map(|(column, generator)| {
+            // the value is drawn even for a null cell, so `nulls` does not shift the other columns
+            let value = generator.generate(rng);
+            if column.nulls > 0.0 && rng.gen_bool(column.nulls) {
+                Value::Null
+            } else {
+                value
+            }
+        })
+        .collect()
+}
+
+impl Spec {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).with_context(|| format!("invalid spec {}", path))
+    }
+}
+
+impl Generator {
+    fn new(column: &ColumnSpec) -> Result<Self> {
+        if !(0.0..=1.0).contains(&column.nulls) {
+            bail!("nulls must be between 0 and 1, got {}", column.nulls);
+        }
+        let generator = match column.gen {
+            GenKind::Name => Generator::Name,
+            GenKind::Country => Generator::Country,
+            GenKind::Int => {
+                let bound = |value: &Option<Value>, default| match value {
+                    None => Ok(default),
+                    Some(value) => value
+                        .as_i64()
+                        .ok_or_else(|| anyhow::anyhow!("expected an integer bound, got {}", value)),
+                };
+                let min = bound(&column.min, 0)?;
+                let max = bound(&column.max, 100)?;
+                if min > max {
+                    bail!("min {} is greater than max {}", min, max);
+                }
+                Generator::Int(min, max)
+            }
+            GenKind::Date => {
+                let bound = |value: &Option<Value>, name| match value {
+                    Some(Value::String(s)) => NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .with_context(|| format!("invalid {} date {:?}", name, s)),
+                    Some(value) => bail!("expected a YYYY-MM-DD {} date, got {}", name, value),
+                    None => bail!("a date column needs min and max"),
+                };
+                let (min, max) = (bound(&column.min, "min")?, bound(&column.max, "max")?);
+                if min > max {
+                    bail!("min {} is greater than max {}", min, max);
+                }
+                let format = column.format.clone().unwrap_or_else(|| "%Y-%m-%d".into());
+                // every date formats the same way, so a format that works for min works for all,
+                // `%H` is a valid item but a date has no hours
+                let mut text = String::new();
+                if StrftimeItems::new(&format).any(|item| item == Item::Error)
+                    || write!(text, "{}", min.format(&format)).is_err()
+                {
+                    bail!("invalid date format {:?}", format);
+                }
+                Generator::Date {
+                    min,
+                    days: (max - min).num_days() as u64,
+                    format,
+                }
+            }
+            GenKind::Regex => {
+                let Some(pattern) = &column.pattern else {
+                    bail!("a regex column needs a pattern");
+                };
+                Generator::Regex(rand_regex::Regex::compile(pattern, MAX_REPEAT)?)
+            }
+            GenKind::Enum => match &column.values {
+                Some(values) if !values.is_empty() => Generator::Enum(values.clone()),
+                _ => bail!("an enum column needs a non-empty enum list"),
+            },
+        };
+        Ok(generator)
+    }
+
+    fn generate(&self, rng: &mut StdRng) -> Value {
+        match self {
+            Generator::Name => {
+                let first = FIRST_NAMES.choose(rng).expect("FIRST_NAMES won't be empty");
+                let last = LAST_NAMES.choose(rng).expect("LAST_NAMES won't be empty");
+                Value::String(format!("{} {}", first, last))
+            }
+            Generator::Country => {
+                Value::from(*COUNTRIES.choose(rng).expect("COUNTRIES won't be empty"))
+            }
+            Generator::Int(min, max) => Value::from(rng.gen_range(*min..=*max)),
+            Generator::Date { min, days, format } => {
+                let date = *min + Days::new(rng.gen_range(0..=*days));
+                Value::String(date.format(format).to_string())
+            }
+            Generator::Regex(regex) => Value::String(rng.sample(regex)),
+            Generator::Enum(values) => values.choose(rng).expect("checked non-empty").clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn generate(seed: u64, rows: usize) -> Vec<Vec<Value>> {
+        let spec = Spec::load("assets/gen.yaml").unwrap();
+        let generators = spec
+            .columns
+            .iter()
+            .map(|c| Generator::new(c).unwrap())
+            .collect::<Vec<_>>();
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..rows)
+            .map(|_| generate_row(&spec.columns, &generators, &mut rng))
+            .collect()
+    }
+
+    #[test]
+    fn test_generate_is_reproducible() {
+        let rows = generate(42, 200);
+        assert_eq!(rows, generate(42, 200));
+        assert_ne!(rows, generate(7, 200));
+        let position = regex::Regex::new("^[A-Z][a-z]{5,8}$").unwrap();
+        for row in &rows {
+            let kit = row[4].as_i64().unwrap();
+            assert!((1..=99).contains(&kit));
+            let dob = NaiveDate::parse_from_str(row[2].as_str().unwrap(), "%m/%d/%Y").unwrap();
+            assert!(dob.to_string().as_str() >= "1920-01-01");
+            assert!(position.is_match(row[1].as_str().unwrap()));
+            assert!(COUNTRIES.contains(&row[3].as_str().unwrap()));
+        }
+
+        let opts =
+            CsvGenOpts::try_parse_from(["gen", "--spec", "assets/gen.yaml", "-n", "3"]).unwrap();
+        assert_eq!(opts.rows, 3);
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        let column = |yaml: &str| {
+            let column: ColumnSpec = serde_yaml::from_str(yaml).unwrap();
+            Generator::new(&column).map(|_| ()).unwrap_err().to_string()
+        };
+        assert_eq!(
+            column("{name: Kit, gen: int, min: 10, max: 1}"),
+            "min 10 is greater than max 1"
+        );
+        assert_eq!(
+            column("{name: DOB, gen: date, min: 2000-01-01}"),
+            "a date column needs min and max"
+        );
+        for format in ["%Q", "%d/%m/%Y %H:%M"] {
+            let yaml = format!(
+                "{{name: DOB, gen: date, min: 2000-01-01, max: 2000-12-31, format: {:?}}}",
+                format
+            );
+            assert_eq!(column(&yaml), format!("invalid date format {:?}", format));
+        }
+        assert_eq!(
+            column("{name: Foot, gen: enum, enum: []}"),
+            "an enum column needs a non-empty enum list"
+        );
+        assert!(serde_yaml::from_str::<ColumnSpec>("{name: Kit, gen: uuid}").is_err());
+    }
+}
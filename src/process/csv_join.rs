@@ -0,0 +1,449 @@
+use anyhow::{anyhow, bail, Result};
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use super::csv_reader::{CsvSource, Rejects};
+use super::csv_types::{
+    file_types, infer_types, keys_as_text, text_key, value_key, TypeConflict, TypeConverter,
+};
+use super::csv_writer::{create_csv_writer, create_row_writer};
+use crate::{get_writer, ColumnType, CsvJoinOpts, CsvReaderOpts, CsvTypeOpts, JoinKind};
+
+/// a csv file opened for the join, its records start with the sample used to infer the types
+struct Input {
+    headers: Vec<String>,
+    types: Vec<ColumnType>,
+    converter: TypeConverter,
+    records: Box<dyn Iterator<Item = Result<StringRecord>>>,
+}
+
+/// the output columns of a join and where they come from
+#[derive(Debug, PartialEq)]
+struct Layout {
+    headers: Vec<String>,
+    left_key: Vec<usize>,
+    right_key: Vec<usize>,
+    // the right columns that are not keys, in file order
+    right_rest: Vec<usize>,
+}
+
+/// process_csv_join joins the first input (left) with the second one (right) on the key columns.
+/// The right file is read into a hash table and the left file is streamed, so put the larger file
+/// on the left. Rows are written in the order of the left file, unmatched right rows of a right or
+/// outer join come last. csv output keeps the cells as they are, the other formats are typed.
+pub fn process_csv_join(opts: &CsvJoinOpts) -> Result<Vec<TypeConflict>> {
+    let [left, right] = opts.inputs.as_slice() else {
+        bail!(
+            "csv join takes exactly two inputs, got {}",
+            opts.inputs.len()
+        );
+    };
+    let left_prefix = opts
+        .left_prefix
+        .clone()
+        .unwrap_or_else(|| default_prefix(left, "left"));
+    let right_prefix = opts
+        .right_prefix
+        .clone()
+        .unwrap_or_else(|| default_prefix(right, "right"));
+
+    let rejects = Rejects::open(&opts.reader)?;
+    let mut left = open(left, &opts.reader, &opts.types, rejects.as_ref())?;
+    let mut right = open(right, &opts.reader, &opts.types, rejects.as_ref())?;
+    let layout = layout(
+        &left.headers,
+        &right.headers,
+        &opts.on,
+        &left_prefix,
+        &right_prefix,
+    )?;
+
+    let writer = get_writer(&opts.output)?;
+    let mut writer = match opts.format {
+        Some(format) => create_row_writer(format, writer, &layout.headers),
+        None => create_csv_writer(writer, opts.reader.delimiter, &layout.headers)?,
+    };
+    join(
+        &mut left,
+        &mut right,
+        &layout,
+        opts.kind,
+        opts.format.is_some(),
+        |row| {
+            let row = layout
+                .headers
+                .iter()
+                .cloned()
+                .zip(row)
+                .collect::<Map<_, _>>();
+            writer.write_row(&row)
+        },
+    )?;
+    writer.finish()?;
+
+    let mut conflicts = left.converter.conflicts();
+    conflicts.extend(right.converter.conflicts());
+    Ok(conflicts)
+}
+
+fn open(
+    input: &str,
+    reader: &CsvReaderOpts,
+    types: &CsvTypeOpts,
+    rejects: Option<&Rejects>,
+) -> Result<Input> {
+    let mut source = CsvSource::open_with_rejects(input, reader, rejects)?;
+    Input::new(&mut source, types).map(|input| input.chain(source))
+}
+
+impl Input {
+    fn new(source: &mut CsvSource, types: &CsvTypeOpts) -> Result<Self> {
+        let headers = source.headers().to_vec();
+        let sample = source
+            .by_ref()
+            .take(types.sample)
+            .collect::<Result<Vec<_>>>()?;
+        // --types and --date apply to the columns of both files, a file may not have all of them
+        let opts = file_types(&headers, types);
+        let types = infer_types(&headers, &sample, &opts)?;
+        let converter = TypeConverter::from_opts(&headers, types.clone(), &opts);
+        Ok(Self {
+            headers,
+            types,
+            converter,
+            records: Box::new(sample.into_iter().map(Ok)),
+        })
+    }
+
+    fn chain(self, rest: impl Iterator<Item = Result<StringRecord>> + 'static) -> Self {
+        Self {
+            records: Box::new(self.records.chain(rest)),
+            ..self
+        }
+    }
+
+    /// the cells of a record, typed or as they are in the file, and its join key. A row with an
+    /// empty key cell never matches, like NULL in SQL. The key columns in `text` are keyed by the
+    /// text of their cells, see [`keys_as_text`].
+    fn row(
+        &mut self,
+        record: &StringRecord,
+        key: &[usize],
+        text: &[bool],
+        typed: bool,
+    ) -> (Vec<Value>, Option<Vec<String>>) {
+        let cells = if typed {
+            self.converter.convert(&self.headers, record)
+        } else {
+            (0..self.headers.len())
+                .map(|i| record.get(i).map_or(Value::Null, Value::from))
+                .collect()
+        };
+        let key = key
+            .iter()
+            .zip(text)
+            .map(|(i, text)| {
+                if *text {
+                    return record.get(*i).filter(|c| !c.is_empty()).map(text_key);
+                }
+                // typed cells were converted already, converting again would count conflicts twice
+                let value = if typed {
+                    cells[*i].clone()
+                } else {
+                    self.converter.convert_cell(&self.headers, record, *i)
+                };
+                match value {
+                    Value::Null => None,
+                    Value::String(s) if s.is_empty() => None,
+                    value => Some(value_key(&value)),
+                }
+            })
+            .collect();
+        (cells, key)
+    }
+}
+
+/// `players.` for `data/players.csv`
+fn default_prefix(input: &str, fallback: &str) -> String {
+    let stem = Path::new(input)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .filter(|_| input != "-")
+        .unwrap_or(fallback);
+    format!("{}.", stem)
+}
+
+/// the output has the left columns, with the key columns in place, then the right columns that
+/// are not keys. A column name that is in both files gets the prefix of its file.
+fn layout(
+    left: &[String],
+    right: &[String],
+    on: &[(String, String)],
+    left_prefix: &str,
+    right_prefix: &str,
+) -> Result<Layout> {
+    let position = |headers: &[String], name: &str, file: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow!("key column {:?} is missing in the {} file", name, file))
+    };
+    let left_key = on
+        .iter()
+        .map(|(l, _)| position(left, l, "left"))
+        .collect::<Result<Vec<_>>>()?;
+    let right_key = on
+        .iter()
+        .map(|(_, r)| position(right, r, "right"))
+        .collect::<Result<Vec<_>>>()?;
+    let right_rest = (0..right.len())
+        .filter(|i| !right_key.contains(i))
+        .collect::<Vec<_>>();
+
+    let mut headers = Vec::with_capacity(left.len() + right_rest.len());
+    for (i, name) in left.iter().enumerate() {
+        let conflict = right_rest.iter().any(|j| &right[*j] == name);
+        if conflict && !left_key.contains(&i) {
+            headers.push(format!("{}{}", left_prefix, name));
+        } else {
+            headers.push(name.clone());
+        }
+    }
+    for j in &right_rest {
+        let name = &right[*j];
+        if left.contains(name) {
+            headers.push(format!("{}{}", right_prefix, name));
+        } else {
+            headers.push(name.clone());
+        }
+    }
+
+    let mut seen = HashSet::with_capacity(headers.len());
+    if let Some(name) = headers.iter().find(|h| !seen.insert(*h)) {
+        bail!(
+            "the joined files both have a column {:?}, set --left-prefix or --right-prefix",
+            name
+        );
+    }
+    Ok(Layout {
+        headers,
+        left_key,
+        right_key,
+        right_rest,
+    })
+}
+
+fn join(
+    left: &mut Input,
+    right: &mut Input,
+    layout: &Layout,
+    kind: JoinKind,
+    typed: bool,
+    mut emit: impl FnMut(Vec<Value>) -> Result<()>,
+) -> Result<()> {
+    let text = layout
+        .left_key
+        .iter()
+        .zip(&layout.right_key)
+        .map(|(l, r)| keys_as_text(left.types[*l], right.types[*r]))
+        .collect::<Vec<_>>();
+    let mut rows = Vec::new();
+    let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+    while let Some(record) = right.records.next() {
+        let (row, key) = right.row(&record?, &layout.right_key, &text, typed);
+        if let Some(key) = key {
+            index.entry(key).or_default().push(rows.len());
+        }
+        rows.push(row);
+    }
+
+    let mut matched = vec![false; rows.len()];
+    while let Some(record) = left.records.next() {
+        let (row, key) = left.row(&record?, &layout.left_key, &text, typed);
+        let found = key.and_then(|key| index.get(&key));
+        match found {
+            Some(found) => {
+                for i in found {
+                    matched[*i] = true;
+                    emit(joined_row(
+                        layout,
+                        Some(&row),
+                        Some(&rows[*i]),
+                        left.headers.len(),
+                    ))?;
+                }
+            }
+            None if matches!(kind, JoinKind::Left | JoinKind::Outer) => {
+                emit(joined_row(layout, Some(&row), None, left.headers.len()))?;
+            }
+            None => {}
+        }
+    }
+
+    if matches!(kind, JoinKind::Right | JoinKind::Outer) {
+        for (row, _) in rows.iter().zip(&matched).filter(|(_, m)| !**m) {
+            emit(joined_row(layout, None, Some(row), left.headers.len()))?;
+        }
+    }
+    Ok(())
+}
+
+/// the output cells of a pair of rows, the cells of a missing side are null except for the key
+/// columns, which come from the side that is there
+fn joined_row(
+    layout: &Layout,
+    left: Option<&[Value]>,
+    right: Option<&[Value]>,
+    left_width: usize,
+) -> Vec<Value> {
+    let mut ret = Vec::with_capacity(layout.headers.len());
+    for i in 0..left_width {
+        let value = match (left, layout.left_key.iter().position(|k| *k == i)) {
+            (Some(left), _) => left[i].clone(),
+            (None, Some(k)) => right.map_or(Value::Null, |r| r[layout.right_key[k]].clone()),
+            (None, None) => Value::Null,
+        };
+        ret.push(value);
+    }
+    for j in &layout.right_rest {
+        ret.push(right.map_or(Value::Null, |r| r[*j].clone()));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    const PLAYERS: &str =
+        "Name,Nationality,Team\nDennis,AR,Juventus\nGlenn,GA,Juventus\nAlex,,Roma\n";
+    const TEAMS: &str =
+        "Code,Team,Region\nAR,Argentina,CONMEBOL\nFL,Finland,UEFA\nAR,Argentina B,CONMEBOL\n";
+
+    fn input(content: &str) -> Input {
+        input_with(content, &CsvTypeOpts::default())
+    }
+
+    fn input_with(content: &str, types: &CsvTypeOpts) -> Input {
+        let reader = CsvReaderOpts::default();
+        let mut source =
+            CsvSource::from_reader(Box::new(Cursor::new(content.to_string())), &reader).unwrap();
+        Input::new(&mut source, types).unwrap().chain(source)
+    }
+
+    fn run(kind: JoinKind) -> (Vec<String>, Vec<Value>) {
+        join_files(PLAYERS, TEAMS, ("Nationality", "Code"), kind)
+    }
+
+    fn join_files(
+        left: &str,
+        right: &str,
+        (l, r): (&str, &str),
+        kind: JoinKind,
+    ) -> (Vec<String>, Vec<Value>) {
+        let (mut left, mut right) = (input(left), input(right));
+        let on = [(l.to_string(), r.to_string())];
+        let layout = layout(&left.headers, &right.headers, &on, "players.", "teams.").unwrap();
+        let mut rows = Vec::new();
+        join(&mut left, &mut right, &layout, kind, true, |row| {
+            rows.push(Value::Array(row));
+            Ok(())
+        })
+        .unwrap();
+        (layout.headers, rows)
+    }
+
+    #[test]
+    fn test_join_kinds() {
+        let (headers, rows) = run(JoinKind::Inner);
+        assert_eq!(
+            headers,
+            [
+                "Name",
+                "Nationality",
+                "players.Team",
+                "teams.Team",
+                "Region"
+            ]
+        );
+        assert_eq!(
+            rows,
+            [
+                json!(["Dennis", "AR", "Juventus", "Argentina", "CONMEBOL"]),
+                json!(["Dennis", "AR", "Juventus", "Argentina B", "CONMEBOL"]),
+            ]
+        );
+
+        let (_, rows) = run(JoinKind::Left);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[2], json!(["Glenn", "GA", "Juventus", null, null]));
+        // an empty key never matches
+        assert_eq!(rows[3], json!(["Alex", null, "Roma", null, null]));
+
+        let (_, rows) = run(JoinKind::Outer);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[4], json!([null, "FL", null, "Finland", "UEFA"]));
+    }
+
+    #[test]
+    fn test_join_mismatched_key_types() {
+        // the left Id is inferred as an int, the right one as a string
+        let left = "Id,Name\n1,Dennis\n2,Glenn\n";
+        let right = "Id,Team\n1,Juventus\n2,Roma\nX3,Lazio\n";
+        let (_, rows) = join_files(left, right, ("Id", "Id"), JoinKind::Inner);
+        assert_eq!(
+            rows,
+            [
+                json!([1, "Dennis", "Juventus"]),
+                json!([2, "Glenn", "Roma"])
+            ]
+        );
+        // ints and floats still compare as numbers
+        let (_, rows) = join_files(left, "Id,Kit\n1.0,17\n", ("Id", "Id"), JoinKind::Inner);
+        assert_eq!(rows, [json!([1, "Dennis", 17])]);
+    }
+
+    #[test]
+    fn test_key_conflicts_are_counted_once() {
+        let types = CsvTypeOpts {
+            sample: 1,
+            ..Default::default()
+        };
+        let mut left = input_with("Id,Name\n1,Dennis\nX,Glenn\n", &types);
+        let mut right = input("Id,Team\n1,Juventus\n");
+        let on = [("Id".to_string(), "Id".to_string())];
+        let layout = layout(&left.headers, &right.headers, &on, "", "").unwrap();
+        join(&mut left, &mut right, &layout, JoinKind::Left, true, |_| {
+            Ok(())
+        })
+        .unwrap();
+        let conflicts = left.converter.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            (conflicts[0].column.as_str(), conflicts[0].count),
+            ("Id", 1)
+        );
+    }
+
+    #[test]
+    fn test_join_layout_errors() {
+        let names = |s: &str| s.split(',').map(String::from).collect::<Vec<_>>();
+        let on = [("Team".to_string(), "Team".to_string())];
+        let err = layout(&names("Name,Team"), &names("Name"), &on, "", "").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"key column "Team" is missing in the right file"#
+        );
+        let err = layout(&names("Name,Team"), &names("Team,Name"), &on, "", "").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(r#"the joined files both have a column "Name""#));
+        assert_eq!(default_prefix("data/teams.csv", "right"), "teams.");
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde_json::{Map, Value};
+
+use super::csv_reader::CsvSource;
+use super::csv_types::{infer_types, TypeConflict, TypeConverter};
+use super::csv_writer::{create_csv_writer, create_row_writer};
+use super::text::{Blake3, KeyLoader, TextSign};
+use crate::{get_writer, CsvMaskOpts};
+
+/// how the cells of a column are masked
+enum Mask {
+    /// the keyed BLAKE3 hash of the cell
+    Hash,
+    Redact,
+    /// the first characters of the cell
+    Truncate(usize),
+}
+
+/// process_csv_mask masks the `--hash`, `--redact` and `--truncate` columns and keeps the others,
+/// empty cells stay empty. The masked cells are strings in the typed formats.
+pub fn process_csv_mask(opts: &CsvMaskOpts) -> Result<Vec<TypeConflict>> {
+    let mut source = CsvSource::open(&opts.input, &opts.reader)?;
+    let headers = source.headers().to_vec();
+    let masks = masks(&headers, opts)?;
+    let signer = opts.key.as_deref().map(Blake3::load).transpose()?;
+
+    let sample = source
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let types = infer_types(&headers, &sample, &opts.types)?;
+    let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+
+    let writer = get_writer(&opts.output)?;
+    let mut writer = match opts.format {
+        Some(format) => create_row_writer(format, writer, &headers),
+        None => create_csv_writer(writer, opts.reader.delimiter, &headers)?,
+    };
+    for record in sample.into_iter().map(Ok).chain(source) {
+        let record = record?;
+        let mut row = Map::with_capacity(headers.len());
+        for (idx, (header, mask)) in headers.iter().zip(&masks).enumerate() {
+            let cell = record.get(idx).unwrap_or_default();
+            let value = match mask {
+                Some(mask) if !cell.is_empty() => {
+                    Value::String(mask_cell(cell, mask, signer.as_ref(), &opts.redact_with)?)
+                }
+                _ if opts.format.is_some() => converter.convert_cell(&headers, &record, idx),
+                _ => Value::from(cell),
+            };
+            row.insert(header.clone(), value);
+        }
+        writer.write_row(&row)?;
+    }
+    writer.finish()?;
+    Ok(converter.conflicts())
+}
+
+/// the mask of every column, a column can only be masked one way
+fn masks(headers: &[String], opts: &CsvMaskOpts) -> Result<Vec<Option<Mask>>> {
+    let mut masks = headers.iter().map(|_| None).collect::<Vec<_>>();
+    let columns = opts
+        .hash
+        .iter()
+        .map(|c| (c, "--hash", Mask::Hash))
+        .chain(opts.redact.iter().map(|c| (c, "--redact", Mask::Redact)))
+        .chain(
+            opts.truncate
+                .iter()
+                .map(|(c, len)| (c, "--truncate", Mask::Truncate(*len))),
+        );
+    for (column, arg, mask) in columns {
+        let Some(idx) = headers.iter().position(|h| h == column) else {
+            bail!("{} refers to unknown column {:?}", arg, column);
+        };
+        if masks[idx].replace(mask).is_some() {
+            bail!("column {:?} is masked more than once", column);
+        }
+    }
+    Ok(masks)
+}
+
+fn mask_cell(
+    cell: &str,
+    mask: &Mask,
+    signer: Option<&Blake3>,
+    redact_with: &str,
+) -> Result<String> {
+    let masked = match mask {
+        Mask::Hash => {
+            let Some(signer) = signer else {
+                bail!("--hash needs a --key");
+            };
+            URL_SAFE_NO_PAD.encode(signer.sign(&mut cell.as_bytes())?)
+        }
+        Mask::Redact => redact_with.to_string(),
+        Mask::Truncate(len) => cell.chars().take(*len).collect(),
+    };
+    Ok(masked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_cells() {
+        let signer = Blake3::new([7; 32]);
+        let hash = |cell| mask_cell(cell, &Mask::Hash, Some(&signer), "").unwrap();
+        assert_eq!(hash("Dennis Harris"), hash("Dennis Harris"));
+        assert_ne!(hash("Dennis Harris"), hash("Glenn Keller"));
+        assert_eq!(hash("Dennis Harris").len(), 43);
+        let other = Blake3::new([8; 32]);
+        assert_ne!(
+            hash("Dennis Harris"),
+            mask_cell("Dennis Harris", &Mask::Hash, Some(&other), "").unwrap()
+        );
+
+        assert_eq!(
+            mask_cell("São Paulo", &Mask::Truncate(2), None, "").unwrap(),
+            "Sã"
+        );
+        assert_eq!(
+            mask_cell("06/26/2055", &Mask::Redact, None, "***").unwrap(),
+            "***"
+        );
+    }
+
+    #[test]
+    fn test_mask_columns() {
+        use clap::Parser;
+        let headers = ["Name", "DOB", "Nationality"].map(String::from);
+        let opts = |args: &[&str]| CsvMaskOpts::try_parse_from([&["mask"], args].concat()).unwrap();
+        let masks = masks(
+            &headers,
+            &opts(&["--redact", "DOB", "--truncate", "Nationality:1"]),
+        )
+        .unwrap();
+        assert!(matches!(
+            masks.as_slice(),
+            [None, Some(Mask::Redact), Some(Mask::Truncate(1))]
+        ));
+        let err = masks_err(&headers, &opts(&["--redact", "DOB,Kit"]));
+        assert_eq!(err, r#"--redact refers to unknown column "Kit""#);
+        let err = masks_err(&headers, &opts(&["--redact", "DOB", "--truncate", "DOB:4"]));
+        assert_eq!(err, r#"column "DOB" is masked more than once"#);
+        assert!(CsvMaskOpts::try_parse_from(["mask", "--hash", "Name"]).is_err());
+    }
+
+    fn masks_err(headers: &[String], opts: &CsvMaskOpts) -> String {
+        masks(headers, opts).map(|_| ()).unwrap_err().to_string()
+    }
+}
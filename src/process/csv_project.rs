@@ -0,0 +1,139 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+use crate::CsvProjectOpts;
+
+/// Projection maps the input columns to the output columns, after `--select`, `--exclude`,
+/// `--order` and `--rename` are applied. Rows are built from it directly, so the cells of
+/// dropped columns are never converted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    // (index of the input column, output column name)
+    columns: Vec<(usize, String)>,
+}
+
+impl Projection {
+    pub fn new(headers: &[String], opts: &CsvProjectOpts) -> Result<Self> {
+        let find = |name: &String| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow!("unknown column {:?}", name))
+        };
+
+        let mut idxs = if opts.select.is_empty() {
+            (0..headers.len()).collect()
+        } else {
+            opts.select.iter().map(find).collect::<Result<Vec<_>>>()?
+        };
+        for name in &opts.exclude {
+            let idx = find(name)?;
+            idxs.retain(|i| *i != idx);
+        }
+        if !opts.order.is_empty() {
+            let front = opts.order.iter().map(find).collect::<Result<Vec<_>>>()?;
+            if let Some(idx) = front.iter().find(|idx| !idxs.contains(idx)) {
+                bail!("--order column {:?} is not in the output", headers[*idx]);
+            }
+            idxs.retain(|idx| !front.contains(idx));
+            idxs.splice(0..0, front);
+        }
+
+        for (old, _) in &opts.rename {
+            find(old)?;
+        }
+        let columns = idxs
+            .into_iter()
+            .map(|idx| {
+                let name = opts
+                    .rename
+                    .iter()
+                    .find(|(old, _)| old == &headers[idx])
+                    .map_or(&headers[idx], |(_, new)| new);
+                (idx, name.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let mut seen = HashSet::new();
+        if let Some((_, name)) = columns.iter().find(|(_, name)| !seen.insert(name)) {
+            bail!("output column {:?} appears more than once", name);
+        }
+        Ok(Self { columns })
+    }
+
+    /// the output column names, in output order
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().map(|(_, name)| name.clone()).collect()
+    }
+
+    /// build an output row, `cell` is called with the input index of every output column
+    pub fn row(&self, mut cell: impl FnMut(usize) -> Value) -> Map<String, Value> {
+        self.columns
+            .iter()
+            .map(|(idx, name)| (name.clone(), cell(*idx)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Vec<String> {
+        ["Name", "Position", "DOB", "Nationality", "Kit Number"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn project(opts: CsvProjectOpts) -> Result<Vec<String>> {
+        let projection = Projection::new(&headers(), &opts)?;
+        Ok(projection
+            .columns
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect())
+    }
+
+    #[test]
+    fn test_select_rename_order() {
+        let opts = CsvProjectOpts {
+            select: vec!["DOB".into(), "Name".into(), "Kit Number".into()],
+            rename: vec![("Kit Number".into(), "kit".into())],
+            ..Default::default()
+        };
+        assert_eq!(project(opts).unwrap(), ["DOB", "Name", "kit"]);
+
+        let opts = CsvProjectOpts {
+            exclude: vec!["Position".into()],
+            order: vec!["Kit Number".into(), "Nationality".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            project(opts).unwrap(),
+            ["Kit Number", "Nationality", "Name", "DOB"]
+        );
+    }
+
+    #[test]
+    fn test_projection_errors() {
+        let opts = CsvProjectOpts {
+            select: vec!["Kit".into()],
+            ..Default::default()
+        };
+        assert!(project(opts).is_err());
+
+        let opts = CsvProjectOpts {
+            rename: vec![("Name".into(), "DOB".into())],
+            ..Default::default()
+        };
+        assert!(project(opts).is_err());
+
+        let opts = CsvProjectOpts {
+            exclude: vec!["DOB".into()],
+            order: vec!["DOB".into()],
+            ..Default::default()
+        };
+        assert!(project(opts).is_err());
+    }
+}
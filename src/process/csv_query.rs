@@ -0,0 +1,1010 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use super::csv_expr::{
+    arith, as_number, is_truthy, AggFunc, ArithOp, CmpOp, ColumnRef, Expr, ExprParser, Token,
+};
+use super::csv_reader::{CsvSource, Rejects};
+use super::csv_table::Table;
+use super::csv_types::{
+    file_types, infer_types, keys_as_text, sort_order, text_key, value_key, value_text,
+    values_type, TypeConflict, TypeConverter,
+};
+use super::csv_writer::create_row_writer;
+use crate::{get_writer, CsvQueryOpts, JoinKind};
+
+/// Query is a parsed SELECT statement:
+///
+/// ```sql
+/// SELECT [DISTINCT] item, ... FROM table [AS alias]
+///   [[INNER | LEFT [OUTER] | RIGHT [OUTER] | FULL [OUTER]] JOIN table [AS alias] ON expr]...
+///   [WHERE expr] [GROUP BY expr, ...] [HAVING expr]
+///   [ORDER BY expr | position | alias [ASC | DESC], ...] [LIMIT n [OFFSET m]]
+/// ```
+#[derive(Debug)]
+struct Query {
+    distinct: bool,
+    items: Vec<SelectItem>,
+    from: TableRef,
+    joins: Vec<Join>,
+    filter: Option<Expr>,
+    group_by: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<(Expr, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+#[derive(Debug)]
+enum SelectItem {
+    /// `*` or `table.*`
+    Wildcard(Option<String>),
+    Expr(Expr, Option<String>),
+}
+
+#[derive(Debug)]
+struct TableRef {
+    name: String,
+    alias: Option<String>,
+}
+
+#[derive(Debug)]
+struct Join {
+    kind: JoinKind,
+    table: TableRef,
+    on: Expr,
+}
+
+/// a csv file loaded in memory with typed cells
+#[derive(Debug)]
+struct Dataset {
+    name: String,
+    headers: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+/// the columns of the joined rows, as (table qualifier, column name)
+type Layout = Vec<(String, String)>;
+
+/// an ORDER BY key, either an output column or an expression of the source row
+enum SortKey {
+    Output(usize),
+    Expr(Expr),
+}
+
+/// the running state of one aggregate in one group
+#[derive(Debug, Clone)]
+struct Accumulator {
+    count: usize,
+    // the sum of the numeric values and how many there were, for SUM and AVG
+    sum: Option<Value>,
+    numbers: usize,
+    // the smallest or largest value, for MIN and MAX
+    best: Value,
+    seen: HashSet<String>,
+}
+
+/// process_csv_query loads every input, runs the query and writes the result as a table or
+/// in the output format. The type conflicts of every input are returned to the caller.
+pub fn process_csv_query(opts: &CsvQueryOpts) -> Result<Vec<TypeConflict>> {
+    let query = parse_query(&opts.sql)?;
+
+    let mut datasets = Vec::with_capacity(opts.inputs.len());
+    let mut conflicts = Vec::new();
+    let rejects = Rejects::open(&opts.reader)?;
+    for (name, path) in &opts.inputs {
+        let mut source = CsvSource::open_with_rejects(path, &opts.reader, rejects.as_ref())?;
+        let headers = source.headers().to_vec();
+        let sample = source
+            .by_ref()
+            .take(opts.types.sample)
+            .collect::<Result<Vec<_>>>()?;
+        // --types and --date may name columns of any of the inputs
+        let types = file_types(&headers, &opts.types);
+        let types = infer_types(&headers, &sample, &types)?;
+        let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+        let rows = sample
+            .into_iter()
+            .map(Ok)
+            .chain(source)
+            .map(|record| Ok(converter.convert(&headers, &record?)))
+            .collect::<Result<Vec<_>>>()?;
+        conflicts.extend(converter.conflicts());
+        datasets.push(Dataset {
+            name: name.clone(),
+            headers,
+            rows,
+        });
+    }
+    if let Some((column, _)) = opts
+        .types
+        .types
+        .iter()
+        .find(|(column, _)| !datasets.iter().any(|d| d.headers.contains(column)))
+    {
+        bail!("unknown column {:?} in --types", column);
+    }
+
+    let (headers, rows) = run_query(query, &datasets)?;
+    let writer = get_writer(&opts.output)?;
+    match opts.format {
+        Some(format) => {
+            let mut writer = create_row_writer(format, writer, &headers);
+            for row in rows {
+                let row = headers.iter().cloned().zip(row).collect::<Map<_, _>>();
+                writer.write_row(&row)?;
+            }
+            writer.finish()?;
+        }
+        None => {
+            let mut table = Table::new(headers);
+            for row in &rows {
+                table.push_row(row);
+            }
+            let mut writer = writer;
+            table.render(&mut writer)?;
+        }
+    }
+    Ok(conflicts)
+}
+
+fn parse_query(sql: &str) -> Result<Query> {
+    let mut parser = ExprParser::new(sql)?;
+    parser.expect_keyword("select")?;
+    let distinct = parser.eat_keyword("distinct");
+
+    let mut items = Vec::new();
+    loop {
+        if parser.eat_symbol("*") {
+            items.push(SelectItem::Wildcard(None));
+        } else if matches!(
+            (parser.peek_nth(1), parser.peek_nth(2)),
+            (Some(Token::Symbol(".")), Some(Token::Symbol("*")))
+        ) {
+            let table = parse_name(&mut parser)?;
+            parser.next_token();
+            parser.next_token();
+            items.push(SelectItem::Wildcard(Some(table)));
+        } else {
+            let expr = parser.parse_expr()?;
+            let alias = if parser.eat_keyword("as") {
+                Some(parser.parse_identifier()?)
+            } else {
+                None
+            };
+            items.push(SelectItem::Expr(expr, alias));
+        }
+        if !parser.eat_symbol(",") {
+            break;
+        }
+    }
+
+    parser.expect_keyword("from")?;
+    let from = parse_table_ref(&mut parser)?;
+    let mut joins = Vec::new();
+    while let Some(kind) = parse_join_kind(&mut parser)? {
+        let table = parse_table_ref(&mut parser)?;
+        parser.expect_keyword("on")?;
+        let on = parser.parse_expr()?;
+        joins.push(Join { kind, table, on });
+    }
+
+    let filter = if parser.eat_keyword("where") {
+        Some(parser.parse_expr()?)
+    } else {
+        None
+    };
+    let mut group_by = Vec::new();
+    if parser.eat_keyword("group") {
+        parser.expect_keyword("by")?;
+        group_by.push(parser.parse_expr()?);
+        while parser.eat_symbol(",") {
+            group_by.push(parser.parse_expr()?);
+        }
+    }
+    let having = if parser.eat_keyword("having") {
+        Some(parser.parse_expr()?)
+    } else {
+        None
+    };
+    let mut order_by = Vec::new();
+    if parser.eat_keyword("order") {
+        parser.expect_keyword("by")?;
+        loop {
+            let expr = parser.parse_expr()?;
+            let desc = parser.eat_keyword("desc");
+            if !desc {
+                parser.eat_keyword("asc");
+            }
+            order_by.push((expr, desc));
+            if !parser.eat_symbol(",") {
+                break;
+            }
+        }
+    }
+    let limit = if parser.eat_keyword("limit") {
+        Some(parse_count(&mut parser, "LIMIT")?)
+    } else {
+        None
+    };
+    let offset = if parser.eat_keyword("offset") {
+        parse_count(&mut parser, "OFFSET")?
+    } else {
+        0
+    };
+    if let Some(token) = parser.peek() {
+        bail!("unexpected {} in query", token);
+    }
+
+    Ok(Query {
+        distinct,
+        items,
+        from,
+        joins,
+        filter,
+        group_by,
+        having,
+        order_by,
+        limit,
+        offset,
+    })
+}
+
+/// a table name or alias is a single word, so `players p` is the table `players` aliased `p`
+fn parse_name(parser: &mut ExprParser) -> Result<String> {
+    match parser.peek().cloned() {
+        Some(Token::QuotedIdent(name)) => {
+            parser.next_token();
+            Ok(name)
+        }
+        Some(Token::Ident(name)) if !is_clause_keyword(&name) => {
+            parser.next_token();
+            Ok(name)
+        }
+        _ => bail!("expected a table name {}", parser.found()),
+    }
+}
+
+fn parse_table_ref(parser: &mut ExprParser) -> Result<TableRef> {
+    let name = parse_name(parser)?;
+    let has_alias = match parser.peek() {
+        Some(Token::QuotedIdent(_)) => true,
+        Some(Token::Ident(word)) => !is_clause_keyword(word) || parser.eat_keyword("as"),
+        _ => false,
+    };
+    let alias = if has_alias {
+        Some(parse_name(parser)?)
+    } else {
+        None
+    };
+    Ok(TableRef { name, alias })
+}
+
+fn parse_join_kind(parser: &mut ExprParser) -> Result<Option<JoinKind>> {
+    let kind = if parser.eat_keyword("join") {
+        return Ok(Some(JoinKind::Inner));
+    } else if parser.eat_keyword("inner") {
+        JoinKind::Inner
+    } else if parser.eat_keyword("left") {
+        JoinKind::Left
+    } else if parser.eat_keyword("right") {
+        JoinKind::Right
+    } else if parser.eat_keyword("full") {
+        JoinKind::Outer
+    } else {
+        return Ok(None);
+    };
+    if kind != JoinKind::Inner {
+        parser.eat_keyword("outer");
+    }
+    parser.expect_keyword("join")?;
+    Ok(Some(kind))
+}
+
+fn parse_count(parser: &mut ExprParser, clause: &str) -> Result<usize> {
+    match parser.next_token() {
+        Some(Token::Number(n)) if n.is_u64() => Ok(n.as_u64().unwrap_or_default() as usize),
+        _ => bail!("{} expects a non-negative integer", clause),
+    }
+}
+
+/// the keywords that may follow a table name
+fn is_clause_keyword(word: &str) -> bool {
+    [
+        "as", "on", "join", "inner", "left", "right", "full", "outer", "where", "group", "having",
+        "order", "limit", "offset",
+    ]
+    .iter()
+    .any(|k| k.eq_ignore_ascii_case(word))
+}
+
+/// run the query over the datasets, returns the output column names and rows
+fn run_query(query: Query, datasets: &[Dataset]) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let Query {
+        distinct,
+        items,
+        from,
+        joins,
+        filter,
+        group_by,
+        having,
+        order_by,
+        limit,
+        offset,
+    } = query;
+
+    // FROM and JOIN build the source rows, every row is the concatenation of the tables' cells
+    let mut qualifiers = Vec::new();
+    let (mut layout, mut rows) = {
+        let (qualifier, dataset) = find_dataset(&from, datasets, &mut qualifiers)?;
+        (qualified(&qualifier, dataset), dataset.rows.clone())
+    };
+    for join in joins {
+        let (qualifier, dataset) = find_dataset(&join.table, datasets, &mut qualifiers)?;
+        let left_width = layout.len();
+        layout.extend(qualified(&qualifier, dataset));
+        let mut on = join.on;
+        if on.has_aggregate() {
+            bail!("aggregates are not allowed in ON");
+        }
+        on.bind(&|column| resolve(&layout, column))?;
+        rows = join_rows(rows, left_width, dataset, on, join.kind);
+    }
+    let width = layout.len();
+    let bind = |expr: &mut Expr| expr.bind(&|column| resolve(&layout, column));
+
+    if let Some(mut filter) = filter {
+        if filter.has_aggregate() {
+            bail!("aggregates are not allowed in WHERE, use HAVING");
+        }
+        bind(&mut filter)?;
+        rows.retain(|row| is_truthy(&filter.eval(row)));
+    }
+
+    // the output columns, named by their alias, column name or expression text
+    let mut names = Vec::new();
+    let mut exprs = Vec::new();
+    for item in items {
+        match item {
+            SelectItem::Wildcard(table) => {
+                if table.as_ref().is_some_and(|t| !qualifiers.contains(t)) {
+                    bail!("unknown table {:?} in SELECT", table.unwrap_or_default());
+                }
+                for (idx, (qualifier, name)) in layout.iter().enumerate() {
+                    if table.as_ref().is_none_or(|t| t == qualifier) {
+                        names.push(name.clone());
+                        exprs.push(Expr::Index(idx));
+                    }
+                }
+            }
+            SelectItem::Expr(expr, alias) => {
+                let name = match (alias, &expr) {
+                    (Some(alias), _) => alias,
+                    (None, Expr::Column(column)) => column.name.clone(),
+                    (None, expr) => expr.to_string(),
+                };
+                names.push(name);
+                exprs.push(expr);
+            }
+        }
+    }
+    let names = unique_names(names);
+
+    // ORDER BY a position or an output name refers to the output column
+    let mut sort_keys = Vec::with_capacity(order_by.len());
+    for (expr, desc) in order_by {
+        let key = match &expr {
+            Expr::Literal(Value::Number(n)) => match n.as_u64() {
+                Some(pos) if pos >= 1 && pos as usize <= names.len() => {
+                    SortKey::Output(pos as usize - 1)
+                }
+                _ => bail!("ORDER BY position {} is out of range", n),
+            },
+            Expr::Column(ColumnRef { table: None, name }) if names.contains(name) => {
+                SortKey::Output(names.iter().position(|n| n == name).unwrap_or_default())
+            }
+            _ => SortKey::Expr(expr),
+        };
+        sort_keys.push((key, desc));
+    }
+
+    // with GROUP BY or aggregates, every group becomes one row: its first source row followed by
+    // the aggregate results, and the aggregates in the expressions are replaced by those columns
+    let grouped = !group_by.is_empty()
+        || exprs.iter().any(Expr::has_aggregate)
+        || having.as_ref().is_some_and(Expr::has_aggregate)
+        || sort_keys
+            .iter()
+            .any(|(key, _)| matches!(key, SortKey::Expr(e) if e.has_aggregate()));
+    let mut having = having;
+    if grouped {
+        let mut aggregates = Vec::new();
+        for expr in exprs.iter_mut().chain(having.iter_mut()) {
+            extract_aggregates(expr, width, &mut aggregates);
+        }
+        for (key, _) in sort_keys.iter_mut() {
+            if let SortKey::Expr(expr) = key {
+                extract_aggregates(expr, width, &mut aggregates);
+            }
+        }
+        let mut group_by = group_by;
+        for expr in group_by.iter_mut() {
+            if expr.has_aggregate() {
+                bail!("aggregates are not allowed in GROUP BY");
+            }
+            bind(expr)?;
+        }
+        for agg in aggregates.iter_mut() {
+            bind(agg)?;
+        }
+        rows = group_rows(rows, width, &group_by, &aggregates);
+    } else if having.is_some() {
+        bail!("HAVING needs GROUP BY or an aggregate");
+    }
+
+    for expr in exprs.iter_mut() {
+        bind(expr)?;
+    }
+    for (key, _) in sort_keys.iter_mut() {
+        if let SortKey::Expr(expr) = key {
+            bind(expr)?;
+        }
+    }
+    if let Some(mut having) = having {
+        bind(&mut having)?;
+        rows.retain(|row| is_truthy(&having.eval(row)));
+    }
+
+    let mut seen = HashSet::new();
+    let mut results = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let output = exprs.iter().map(|e| e.eval(row)).collect::<Vec<_>>();
+        if distinct && !seen.insert(output.iter().map(value_key).collect::<Vec<_>>()) {
+            continue;
+        }
+        let keys = sort_keys
+            .iter()
+            .map(|(key, _)| match key {
+                SortKey::Output(idx) => output[*idx].clone(),
+                SortKey::Expr(expr) => expr.eval(row),
+            })
+            .collect::<Vec<_>>();
+        results.push((output, keys));
+    }
+    // a stable sort keeps the input order of equal rows
+    results.sort_by(|(_, a), (_, b)| {
+        a.iter()
+            .zip(b)
+            .zip(&sort_keys)
+            .map(|((a, b), (_, desc))| {
+                let ord = sort_order(a, b);
+                if *desc {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            })
+            .find(|ord| *ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let rows = results
+        .into_iter()
+        .map(|(output, _)| output)
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+    Ok((names, rows))
+}
+
+/// find the input of a FROM or JOIN table, with a single input any table name refers to it
+fn find_dataset<'a>(
+    table: &TableRef,
+    datasets: &'a [Dataset],
+    qualifiers: &mut Vec<String>,
+) -> Result<(String, &'a Dataset)> {
+    let dataset = match datasets.iter().find(|d| d.name == table.name) {
+        Some(dataset) => dataset,
+        None if datasets.len() == 1 => &datasets[0],
+        None => {
+            let names = datasets.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+            bail!("unknown table {:?}, the inputs are {:?}", table.name, names);
+        }
+    };
+    let qualifier = table.alias.clone().unwrap_or_else(|| table.name.clone());
+    if qualifiers.contains(&qualifier) {
+        bail!("table {:?} is used twice, give it an alias", qualifier);
+    }
+    qualifiers.push(qualifier.clone());
+    Ok((qualifier, dataset))
+}
+
+fn qualified(qualifier: &str, dataset: &Dataset) -> Layout {
+    dataset
+        .headers
+        .iter()
+        .map(|h| (qualifier.to_string(), h.clone()))
+        .collect()
+}
+
+fn resolve(layout: &Layout, column: &ColumnRef) -> Result<usize> {
+    let mut found = layout.iter().enumerate().filter(|(_, (table, name))| {
+        name == &column.name && column.table.as_ref().is_none_or(|t| t == table)
+    });
+    match (found.next(), found.next()) {
+        (Some((idx, _)), None) => Ok(idx),
+        (None, _) => Err(anyhow!("unknown column {:?}", column.to_string())),
+        (Some(_), Some(_)) => bail!(
+            "column {:?} is ambiguous, qualify it with a table name",
+            column.name
+        ),
+    }
+}
+
+/// join the rows with the rows of the next table. The `a = b` conditions of the ON clause that
+/// compare the left side to the right side are used as the key of a hash join, the rest of the
+/// condition is checked on every candidate pair. Without such a condition it is a nested loop.
+fn join_rows(
+    left: Vec<Vec<Value>>,
+    left_width: usize,
+    right: &Dataset,
+    on: Expr,
+    kind: JoinKind,
+) -> Vec<Vec<Value>> {
+    let mut conjuncts = Vec::new();
+    split_and(on, &mut conjuncts);
+    let mut left_keys = Vec::new();
+    let mut right_keys = Vec::new();
+    let mut residual = Vec::new();
+    for expr in conjuncts {
+        match expr {
+            Expr::Compare(CmpOp::Eq, a, b) => match (side(&a, left_width), side(&b, left_width)) {
+                (Some(true), Some(false)) => {
+                    left_keys.push(*a);
+                    right_keys.push(shift(*b, left_width));
+                }
+                (Some(false), Some(true)) => {
+                    left_keys.push(*b);
+                    right_keys.push(shift(*a, left_width));
+                }
+                _ => residual.push(Expr::Compare(CmpOp::Eq, a, b)),
+            },
+            expr => residual.push(expr),
+        }
+    }
+
+    // rows with a null in the key never match, like in SQL
+    let values = |exprs: &[Expr], row: &[Value]| -> Option<Vec<Value>> {
+        exprs
+            .iter()
+            .map(|e| Some(e.eval(row)).filter(|v| !v.is_null()))
+            .collect()
+    };
+    let left_values = left
+        .iter()
+        .map(|row| values(&left_keys, row))
+        .collect::<Vec<_>>();
+    let right_values = right
+        .rows
+        .iter()
+        .map(|row| values(&right_keys, row))
+        .collect::<Vec<_>>();
+    // the tables infer their types on their own, a key that is a number in one and a string in
+    // the other is compared by its text
+    let text = (0..left_keys.len())
+        .map(|k| {
+            let column = |values: &[Option<Vec<Value>>]| {
+                values_type(values.iter().flatten().map(|key| &key[k]))
+            };
+            keys_as_text(column(&left_values), column(&right_values))
+        })
+        .collect::<Vec<_>>();
+    let key = |values: &Option<Vec<Value>>| -> Option<Vec<String>> {
+        let values = values.as_ref()?;
+        let key = values.iter().zip(&text).map(|(value, text)| {
+            if *text {
+                text_key(&value_text(value))
+            } else {
+                value_key(value)
+            }
+        });
+        Some(key.collect())
+    };
+    let mut index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+    if !left_keys.is_empty() {
+        for (i, values) in right_values.iter().enumerate() {
+            if let Some(key) = key(values) {
+                index.entry(key).or_default().push(i);
+            }
+        }
+    }
+    let (right_width, right) = (right.headers.len(), &right.rows);
+    let all = (0..right.len()).collect::<Vec<_>>();
+
+    let mut ret = Vec::new();
+    let mut right_matched = vec![false; right.len()];
+    for (row, values) in left.into_iter().zip(&left_values) {
+        let candidates = if left_keys.is_empty() {
+            &all[..]
+        } else {
+            key(values)
+                .and_then(|key| index.get(&key))
+                .map_or(&[][..], |c| &c[..])
+        };
+        let mut matched = false;
+        for &i in candidates {
+            let mut joined = row.clone();
+            joined.extend(right[i].iter().cloned());
+            if residual.iter().all(|e| is_truthy(&e.eval(&joined))) {
+                matched = true;
+                right_matched[i] = true;
+                ret.push(joined);
+            }
+        }
+        if !matched && matches!(kind, JoinKind::Left | JoinKind::Outer) {
+            let mut joined = row;
+            joined.resize(left_width + right_width, Value::Null);
+            ret.push(joined);
+        }
+    }
+    if matches!(kind, JoinKind::Right | JoinKind::Outer) {
+        for (row, _) in right.iter().zip(&right_matched).filter(|(_, m)| !**m) {
+            let mut joined = vec![Value::Null; left_width];
+            joined.extend(row.iter().cloned());
+            ret.push(joined);
+        }
+    }
+    ret
+}
+
+fn split_and(expr: Expr, ret: &mut Vec<Expr>) {
+    match expr {
+        Expr::And(a, b) => {
+            split_and(*a, ret);
+            split_and(*b, ret);
+        }
+        expr => ret.push(expr),
+    }
+}
+
+/// Some(true) if the expression only reads the left table, Some(false) if it only reads the right
+fn side(expr: &Expr, left_width: usize) -> Option<bool> {
+    let mut columns = Vec::new();
+    expr.columns(&mut columns);
+    if columns.is_empty() {
+        None
+    } else if columns.iter().all(|idx| *idx < left_width) {
+        Some(true)
+    } else if columns.iter().all(|idx| *idx >= left_width) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// rebase an expression of the right side so it can be evaluated on the right rows alone
+fn shift(mut expr: Expr, offset: usize) -> Expr {
+    fn walk(expr: &mut Expr, offset: usize) {
+        if let Expr::Index(idx) = expr {
+            *idx -= offset;
+        }
+        for child in expr.children_mut() {
+            walk(child, offset);
+        }
+    }
+    walk(&mut expr, offset);
+    expr
+}
+
+/// replace the aggregates with the columns their results are appended at, equal aggregates
+/// (like the `COUNT(*)` in SELECT and in ORDER BY) share a column
+fn extract_aggregates(expr: &mut Expr, width: usize, aggregates: &mut Vec<Expr>) {
+    if let Expr::Aggregate(..) = expr {
+        let text = expr.to_string();
+        let idx = match aggregates.iter().position(|a| a.to_string() == text) {
+            Some(idx) => idx,
+            None => {
+                aggregates.push(expr.clone());
+                aggregates.len() - 1
+            }
+        };
+        *expr = Expr::Index(width + idx);
+        return;
+    }
+    for child in expr.children_mut() {
+        extract_aggregates(child, width, aggregates);
+    }
+}
+
+/// group the rows by the key expressions, in the order the groups first appear. Without
+/// GROUP BY all rows are one group, which exists even if there are no rows so COUNT(*) is 0.
+fn group_rows(
+    rows: Vec<Vec<Value>>,
+    width: usize,
+    group_by: &[Expr],
+    aggregates: &[Expr],
+) -> Vec<Vec<Value>> {
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut groups: Vec<(Vec<Value>, Vec<Accumulator>)> = Vec::new();
+    if group_by.is_empty() {
+        groups.push((
+            vec![Value::Null; width],
+            vec![Accumulator::default(); aggregates.len()],
+        ));
+        index.insert(Vec::new(), 0);
+    }
+    for row in rows {
+        let key = group_by
+            .iter()
+            .map(|e| value_key(&e.eval(&row)))
+            .collect::<Vec<_>>();
+        let idx = *index.entry(key).or_insert_with(|| {
+            groups.push((row.clone(), vec![Accumulator::default(); aggregates.len()]));
+            groups.len() - 1
+        });
+        for (acc, agg) in groups[idx].1.iter_mut().zip(aggregates) {
+            acc.update(agg, &row);
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(mut row, accs)| {
+            row.extend(
+                accs.iter()
+                    .zip(aggregates)
+                    .map(|(acc, agg)| acc.result(agg)),
+            );
+            row
+        })
+        .collect()
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: None,
+            numbers: 0,
+            best: Value::Null,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl Accumulator {
+    /// add a row to the aggregate, nulls are skipped and `*` counts every row
+    fn update(&mut self, agg: &Expr, row: &[Value]) {
+        let Expr::Aggregate(func, arg, distinct) = agg else {
+            return;
+        };
+        let value = match arg {
+            Some(arg) => arg.eval(row),
+            None => Value::Bool(true),
+        };
+        if value.is_null() || (*distinct && !self.seen.insert(value_key(&value))) {
+            return;
+        }
+        self.count += 1;
+        match func {
+            AggFunc::Count => {}
+            AggFunc::Sum | AggFunc::Avg => {
+                if as_number(&value).is_some() {
+                    let sum = self.sum.take().unwrap_or(Value::from(0));
+                    self.sum = Some(arith(ArithOp::Add, &sum, &value));
+                    self.numbers += 1;
+                }
+            }
+            AggFunc::Min | AggFunc::Max => {
+                let ord = sort_order(&value, &self.best);
+                let better = if *func == AggFunc::Min {
+                    ord == Ordering::Less
+                } else {
+                    ord == Ordering::Greater
+                };
+                if self.best.is_null() || better {
+                    self.best = value;
+                }
+            }
+        }
+    }
+
+    fn result(&self, agg: &Expr) -> Value {
+        let Expr::Aggregate(func, _, _) = agg else {
+            return Value::Null;
+        };
+        match func {
+            AggFunc::Count => Value::from(self.count),
+            AggFunc::Sum => self.sum.clone().unwrap_or(Value::Null),
+            AggFunc::Avg => match self.sum.as_ref().and_then(as_number) {
+                Some(sum) if self.numbers > 0 => Value::from(sum / self.numbers as f64),
+                _ => Value::Null,
+            },
+            AggFunc::Min | AggFunc::Max => self.best.clone(),
+        }
+    }
+}
+
+/// make the output names unique, a repeated name gets a `_2`, `_3`, ... suffix
+fn unique_names(names: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while !seen.insert(unique.clone()) {
+                n += 1;
+                unique = format!("{}_{}", name, n);
+            }
+            unique
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dataset(name: &str, headers: &[&str], rows: Value) -> Dataset {
+        let rows = rows
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row.as_array().unwrap().clone())
+            .collect();
+        Dataset {
+            name: name.into(),
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows,
+        }
+    }
+
+    fn datasets() -> Vec<Dataset> {
+        vec![
+            dataset(
+                "players",
+                &["Name", "Nationality", "Kit Number"],
+                json!([
+                    ["Dennis Harris", "AR", 17],
+                    ["Glenn Keller", "GA", 92],
+                    ["Rhoda Lane", "AR", 3],
+                    ["Ina Ortiz", "FL", null],
+                    ["Eva Moss", "XX", 8]
+                ]),
+            ),
+            dataset(
+                "teams",
+                &["Nationality", "Team"],
+                json!([
+                    ["AR", "Argentina"],
+                    ["GA", "Gabon"],
+                    ["FL", "Florida"],
+                    ["ZZ", "Nowhere"]
+                ]),
+            ),
+        ]
+    }
+
+    fn query(sql: &str) -> Result<(Vec<String>, Vec<Value>)> {
+        let (headers, rows) = run_query(parse_query(sql)?, &datasets())?;
+        Ok((headers, rows.into_iter().map(Value::Array).collect()))
+    }
+
+    #[test]
+    fn test_group_by_order_by_position() {
+        let (headers, rows) = query(
+            "SELECT Nationality, COUNT(*), SUM(Kit Number) AS kits FROM players \
+             GROUP BY Nationality ORDER BY 2 DESC, Nationality LIMIT 2",
+        )
+        .unwrap();
+        assert_eq!(headers, ["Nationality", "COUNT(*)", "kits"]);
+        assert_eq!(rows, [json!(["AR", 2, 20]), json!(["FL", 1, null])]);
+
+        let (_, rows) =
+            query("SELECT COUNT(Kit Number), AVG(Kit Number), MAX(Name) FROM players").unwrap();
+        assert_eq!(rows, [json!([4, 30.0, "Rhoda Lane"])]);
+
+        let (_, rows) =
+            query("SELECT Nationality FROM players GROUP BY Nationality HAVING COUNT(*) > 1")
+                .unwrap();
+        assert_eq!(rows, [json!(["AR"])]);
+
+        // 64-bit ids and zero-padded codes are grouped by their exact value
+        let ids = dataset(
+            "t",
+            &["id", "zip"],
+            json!([
+                [1234567890123456789_i64, "02134"],
+                [1234567890123456788_i64, "2134"],
+                [1234567890123456789_i64, "02134"]
+            ]),
+        );
+        let (_, rows) = run_query(
+            parse_query("SELECT id, zip, COUNT(*) FROM t GROUP BY id, zip ORDER BY id").unwrap(),
+            &[ids],
+        )
+        .unwrap();
+        assert_eq!(
+            rows,
+            [
+                json!([1234567890123456788_i64, "2134", 1])
+                    .as_array()
+                    .unwrap()
+                    .clone(),
+                json!([1234567890123456789_i64, "02134", 2])
+                    .as_array()
+                    .unwrap()
+                    .clone(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_where_distinct_offset() {
+        let (_, rows) = query(
+            "SELECT DISTINCT Nationality FROM players WHERE Kit Number < 50 ORDER BY Nationality DESC OFFSET 1",
+        )
+        .unwrap();
+        assert_eq!(rows, [json!(["AR"])]);
+    }
+
+    #[test]
+    fn test_joins() {
+        let (headers, rows) = query(
+            "SELECT p.Name, t.Team FROM players p JOIN teams t ON p.Nationality = t.Nationality",
+        )
+        .unwrap();
+        assert_eq!(headers, ["Name", "Team"]);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], json!(["Dennis Harris", "Argentina"]));
+
+        let (_, rows) = query(
+            "SELECT Name, Team FROM players AS p LEFT JOIN teams AS t ON p.Nationality = t.Nationality \
+             WHERE Team IS NULL",
+        )
+        .unwrap();
+        assert_eq!(rows, [json!(["Eva Moss", null])]);
+
+        let (headers, rows) = query(
+            "SELECT * FROM players FULL OUTER JOIN teams ON players.Nationality = teams.Nationality \
+             ORDER BY Team",
+        )
+        .unwrap();
+        assert_eq!(headers[1], "Nationality");
+        assert_eq!(headers[3], "Nationality_2");
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[0][0], json!("Eva Moss"));
+        assert_eq!(rows[5], json!([null, null, null, "ZZ", "Nowhere"]));
+
+        // the Id of l is inferred as an int, the one of r as a string
+        let l = dataset("l", &["Id", "Name"], json!([[1, "Dennis"], [2, "Glenn"]]));
+        let r = dataset(
+            "r",
+            &["Id", "Team"],
+            json!([["1", "Juventus"], ["2", "Roma"], ["X3", "Lazio"]]),
+        );
+        let (_, rows) = run_query(
+            parse_query("SELECT Name, Team FROM l JOIN r ON l.Id = r.Id").unwrap(),
+            &[l, r],
+        )
+        .unwrap();
+        assert_eq!(
+            rows.into_iter().map(Value::Array).collect::<Vec<_>>(),
+            [json!(["Dennis", "Juventus"]), json!(["Glenn", "Roma"])]
+        );
+    }
+
+    #[test]
+    fn test_query_errors() {
+        assert!(query("SELECT Nationality FROM players JOIN teams ON Team = 'x'").is_err());
+        assert!(query("SELECT Name FROM players WHERE COUNT(*) > 1").is_err());
+        assert!(query("SELECT Name FROM players ORDER BY 3").is_err());
+        assert!(query("SELECT Name FROM clubs").is_err());
+        assert!(query("SELECT Name FROM players LIMIT").is_err());
+    }
+}
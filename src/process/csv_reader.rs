@@ -0,0 +1,578 @@
+use anyhow::{anyhow, bail, Result};
+use csv::{Position, Reader, ReaderBuilder, StringRecord};
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::{
+    cell::RefCell,
+    io::{Cursor, Read, Write},
+    rc::Rc,
+};
+
+use crate::{get_reader, get_writer, CsvReaderOpts, OnError};
+
+/// CsvSource wraps a [`csv::Reader`] built from [`CsvReaderOpts`] and resolves the column names,
+/// either from the header row, from `--columns`, or as `col1`, `col2`, ... for headerless files.
+/// It is an iterator of records, so every csv command reads its input the same way.
+///
+/// Inputs in other encodings than UTF-8 are transcoded before parsing, the encoding comes from
+/// `--encoding` or from a BOM.
+pub struct CsvSource {
+    reader: Reader<Box<dyn Read>>,
+    headers: Vec<String>,
+    // for headerless files we have to read the first record to know how many columns there are,
+    // it is kept here and handed out before the rest of the records
+    first: Option<StringRecord>,
+    // the transcoder replaces invalid bytes with U+FFFD, these are looked for in every record
+    // unless the input is read as UTF-8 as it is, then the csv parser rejects invalid bytes
+    transcoded: bool,
+    lossy: bool,
+    delimiter: u8,
+    encoding: &'static str,
+    // the first lines that had replaced bytes in lossy mode, and how many there were in all
+    replaced: Vec<u64>,
+    replaced_lines: usize,
+    on_error: OnError,
+    // the `--rejects` file of `--on-error collect`, its records say which input they come from
+    rejects: Option<Rejects>,
+    input: String,
+    rejected: usize,
+}
+
+/// Rejects is the `--rejects` file of `--on-error collect`. A command that reads several inputs
+/// opens it once and shares it, so the rejected records of every input end up in the same file.
+#[derive(Clone)]
+pub struct Rejects(Rc<RefCell<csv::Writer<Box<dyn Write>>>>);
+
+/// a malformed record, where it starts and what is wrong with it
+struct Malformed {
+    line: u64,
+    byte: u64,
+    reason: String,
+    // how to fix it, added to the error in `--on-error fail` mode
+    hint: &'static str,
+    record: Option<StringRecord>,
+}
+
+/// the hint for bytes that are not valid in the input encoding
+const ENCODING_HINT: &str = ", set --encoding (e.g. windows-1252) or use --lossy";
+
+/// replaced lines listed in the warning, the rest are counted
+const MAX_REPORTED_LINES: usize = 20;
+
+impl CsvSource {
+    /// open the input file, if input is "-", it will read from stdin.
+    pub fn open(input: &str, opts: &CsvReaderOpts) -> Result<Self> {
+        let mut source = Self::from_reader(get_reader(input)?, opts)?;
+        source.input = input.to_string();
+        Ok(source)
+    }
+
+    /// open the input like [`CsvSource::open`], rejected records go to the shared rejects file
+    pub fn open_with_rejects(
+        input: &str,
+        opts: &CsvReaderOpts,
+        rejects: Option<&Rejects>,
+    ) -> Result<Self> {
+        let mut source = Self::new(get_reader(input)?, opts)?;
+        source.rejects = rejects.cloned();
+        source.input = input.to_string();
+        Ok(source)
+    }
+
+    pub fn from_reader(reader: Box<dyn Read>, opts: &CsvReaderOpts) -> Result<Self> {
+        let mut source = Self::new(reader, opts)?;
+        source.rejects = Rejects::open(opts)?;
+        Ok(source)
+    }
+
+    fn new(reader: Box<dyn Read>, opts: &CsvReaderOpts) -> Result<Self> {
+        // the BOM is sniffed here as well, the decoder does not tell which encoding it picked
+        let (bom, reader) = sniff_bom(reader)?;
+        // without --encoding a file with no BOM is passed through, --lossy decodes it as UTF-8
+        let encoding = opts.encoding.or(opts.lossy.then_some(encoding_rs::UTF_8));
+        let reader: Box<dyn Read> = Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(encoding)
+                .bom_override(true)
+                .strip_bom(true)
+                .build(reader),
+        );
+        // records with the wrong number of fields are checked by `next`, so they can be skipped
+        let mut reader = ReaderBuilder::new()
+            .delimiter(opts.delimiter)
+            .has_headers(opts.header)
+            .flexible(true)
+            .from_reader(reader);
+
+        let mut first = None;
+        let width = if opts.header {
+            reader.headers().map_err(read_error)?.len()
+        } else {
+            let mut record = StringRecord::new();
+            if reader.read_record(&mut record).map_err(read_error)? {
+                let width = record.len();
+                first = Some(record);
+                width
+            } else {
+                opts.columns.len()
+            }
+        };
+
+        let headers = if opts.columns.is_empty() {
+            if opts.header {
+                reader.headers()?.iter().map(String::from).collect()
+            } else {
+                (1..=width).map(|i| format!("col{}", i)).collect()
+            }
+        } else {
+            if opts.columns.len() != width {
+                bail!(
+                    "--columns lists {} names but the input has {} columns",
+                    opts.columns.len(),
+                    width
+                );
+            }
+            opts.columns.clone()
+        };
+
+        let mut source = Self {
+            reader,
+            headers,
+            first,
+            transcoded: encoding.is_some() || bom.is_some(),
+            lossy: opts.lossy,
+            delimiter: opts.delimiter,
+            // a BOM always wins
+            encoding: bom.or(encoding).map_or("UTF-8", |e| e.name()),
+            replaced: Vec::new(),
+            replaced_lines: 0,
+            on_error: opts.on_error,
+            rejects: None,
+            input: "-".into(),
+            rejected: 0,
+        };
+        if opts.header {
+            let headers = source.reader.headers()?.clone();
+            if let Some(malformed) = source.check(&headers) {
+                bail!(malformed.error());
+            }
+        }
+        Ok(source)
+    }
+
+    /// column names of the input, in file order
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// the number of malformed records that were skipped or collected
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    /// find what is wrong with a record: the wrong number of fields, or bytes that the transcoder
+    /// replaced. Replaced bytes are fine in lossy mode, their line is reported at the end.
+    fn check(&mut self, record: &StringRecord) -> Option<Malformed> {
+        let position = record.position().cloned().unwrap_or_else(Position::new);
+        let line = position.line().max(1);
+        let malformed = |reason: String, hint| Malformed {
+            line,
+            byte: position.byte(),
+            reason,
+            hint,
+            record: Some(record.clone()),
+        };
+        if record.len() != self.headers.len() {
+            let reason = format!(
+                "expected {} fields, found {}",
+                self.headers.len(),
+                record.len()
+            );
+            return Some(malformed(reason, ""));
+        }
+        if !self.transcoded || !record.iter().any(|cell| cell.contains('\u{FFFD}')) {
+            return None;
+        }
+        if !self.lossy {
+            let reason = format!("bytes that are not valid {}", self.encoding);
+            return Some(malformed(reason, ENCODING_HINT));
+        }
+        if self.replaced.len() < MAX_REPORTED_LINES {
+            self.replaced.push(line);
+        }
+        self.replaced_lines += 1;
+        None
+    }
+
+    /// fail, or skip the record and count it, `--on-error collect` also writes it to the rejects
+    fn reject(&mut self, malformed: Malformed) -> Result<()> {
+        if self.on_error == OnError::Fail {
+            bail!(malformed.error());
+        }
+        self.rejected += 1;
+        if let Some(rejects) = &self.rejects {
+            let record = match &malformed.record {
+                Some(record) => record_text(record, self.delimiter)?,
+                None => String::new(),
+            };
+            rejects.0.borrow_mut().write_record([
+                self.input.clone(),
+                malformed.line.to_string(),
+                malformed.byte.to_string(),
+                malformed.reason,
+                record,
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// read the next record, invalid UTF-8 is a malformed record but I/O errors always fail
+    fn read(&mut self) -> Result<Option<Result<StringRecord, Malformed>>> {
+        if let Some(record) = self.first.take() {
+            return Ok(Some(Ok(record)));
+        }
+        let mut record = StringRecord::new();
+        let e = match self.reader.read_record(&mut record) {
+            Ok(found) => return Ok(found.then_some(Ok(record))),
+            Err(e) => e,
+        };
+        let csv::ErrorKind::Utf8 { pos, err } = e.kind() else {
+            return Err(e.into());
+        };
+        let position = pos.clone().unwrap_or_else(Position::new);
+        let column = self
+            .headers
+            .get(err.field())
+            .map_or_else(|| (err.field() + 1).to_string(), |h| format!("{:?}", h));
+        Ok(Some(Err(Malformed {
+            line: position.line(),
+            byte: position.byte(),
+            reason: format!("invalid UTF-8 in column {}", column),
+            hint: ENCODING_HINT,
+            record: None,
+        })))
+    }
+}
+
+impl Malformed {
+    fn error(&self) -> anyhow::Error {
+        anyhow!(
+            "line {} (byte {}): {}{}",
+            self.line,
+            self.byte,
+            self.reason,
+            self.hint
+        )
+    }
+}
+
+/// the cells of a record as a line of csv
+fn record_text(record: &StringRecord, delimiter: u8) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    writer.write_record(record)?;
+    let text = String::from_utf8(writer.into_inner().map_err(|e| e.into_error())?)?;
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
+
+impl Rejects {
+    /// create the rejects file with its header row, there is none unless `--on-error collect`
+    pub fn open(opts: &CsvReaderOpts) -> Result<Option<Self>> {
+        if opts.on_error != OnError::Collect {
+            return Ok(None);
+        }
+        let mut writer = csv::Writer::from_writer(get_writer(&opts.rejects)?);
+        writer.write_record(["file", "line", "byte", "reason", "record"])?;
+        Ok(Some(Self(Rc::new(RefCell::new(writer)))))
+    }
+}
+
+/// expand the glob patterns among the inputs, the files of a pattern are in alphabetical order
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<String>> {
+    let mut ret = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) {
+            ret.push(input.clone());
+            continue;
+        }
+        let before = ret.len();
+        for path in glob::glob(input)? {
+            ret.push(path?.to_string_lossy().into_owned());
+        }
+        if ret.len() == before {
+            bail!("no file matches {:?}", input);
+        }
+    }
+    Ok(ret)
+}
+
+/// the encoding of the BOM the input starts with, and the input with the bytes that were read
+fn sniff_bom(mut reader: Box<dyn Read>) -> Result<(Option<&'static Encoding>, Box<dyn Read>)> {
+    let mut head = Vec::with_capacity(3);
+    reader.by_ref().take(3).read_to_end(&mut head)?;
+    let bom = Encoding::for_bom(&head).map(|(encoding, _)| encoding);
+    Ok((bom, Box::new(Cursor::new(head).chain(reader))))
+}
+
+/// invalid UTF-8 most likely means the file is in another encoding, so the error says how to read it
+fn read_error(e: csv::Error) -> anyhow::Error {
+    if matches!(e.kind(), csv::ErrorKind::Utf8 { .. }) {
+        anyhow!("{}{}", e, ENCODING_HINT)
+    } else {
+        e.into()
+    }
+}
+
+// the commands consume the source as an iterator, so it reports the replaced lines itself
+impl Drop for CsvSource {
+    fn drop(&mut self) {
+        if self.replaced.is_empty() {
+            return;
+        }
+        let mut lines = self
+            .replaced
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        if self.replaced_lines > self.replaced.len() {
+            lines.push(format!(
+                "and {} more",
+                self.replaced_lines - self.replaced.len()
+            ));
+        }
+        eprintln!(
+            "warning: invalid {} bytes were replaced with U+FFFD on line(s) {}",
+            self.encoding,
+            lines.join(", ")
+        );
+    }
+}
+
+impl Iterator for CsvSource {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let malformed = match self.read() {
+                Ok(Some(Ok(record))) => match self.check(&record) {
+                    Some(malformed) => malformed,
+                    None => return Some(Ok(record)),
+                },
+                Ok(Some(Err(malformed))) => malformed,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Err(e) = self.reject(malformed) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(data: &'static str, opts: &CsvReaderOpts) -> CsvSource {
+        CsvSource::from_reader(Box::new(data.as_bytes()), opts).unwrap()
+    }
+
+    #[test]
+    fn test_semicolon_delimiter() {
+        let opts = CsvReaderOpts {
+            delimiter: b';',
+            ..Default::default()
+        };
+        let mut src = source("Name;Kit Number\nDennis;17\n", &opts);
+        assert_eq!(src.headers(), ["Name", "Kit Number"]);
+        let record = src.next().unwrap().unwrap();
+        assert_eq!(record.iter().collect::<Vec<_>>(), ["Dennis", "17"]);
+        assert!(src.next().is_none());
+    }
+
+    #[test]
+    fn test_headerless_generates_names() {
+        let opts = CsvReaderOpts {
+            header: false,
+            ..Default::default()
+        };
+        let src = source("Dennis,17\nGlenn,92\n", &opts);
+        assert_eq!(src.headers(), ["col1", "col2"]);
+        assert_eq!(src.count(), 2);
+    }
+
+    #[test]
+    fn test_columns_override() {
+        let opts = CsvReaderOpts {
+            header: false,
+            columns: vec!["name".into(), "kit".into()],
+            ..Default::default()
+        };
+        let src = source("Dennis,17\n", &opts);
+        assert_eq!(src.headers(), ["name", "kit"]);
+
+        let opts = CsvReaderOpts {
+            columns: vec!["name".into()],
+            ..Default::default()
+        };
+        assert!(CsvSource::from_reader(Box::new(&b"a,b\n1,2\n"[..]), &opts).is_err());
+    }
+
+    #[test]
+    fn test_transcode_input() {
+        // UTF-16LE with BOM, as written by Excel's "Unicode text"
+        let mut data = vec![0xFF, 0xFE];
+        data.extend(
+            "Name,Nationality\nJosé,AR\n"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes),
+        );
+        let mut src =
+            CsvSource::from_reader(Box::new(Cursor::new(data)), &Default::default()).unwrap();
+        assert_eq!(src.headers(), ["Name", "Nationality"]);
+        assert_eq!(&src.next().unwrap().unwrap()[0], "José");
+
+        let opts = CsvReaderOpts {
+            encoding: Some(encoding_rs::WINDOWS_1252),
+            ..Default::default()
+        };
+        let data = b"Name\nJos\xe9 \x80\n".to_vec();
+        let mut src = CsvSource::from_reader(Box::new(Cursor::new(data)), &opts).unwrap();
+        assert_eq!(&src.next().unwrap().unwrap()[0], "José €");
+    }
+
+    #[test]
+    fn test_bom_input_with_invalid_bytes() {
+        // UTF-16LE with BOM and an unpaired surrogate on line 3
+        let data = || {
+            let mut data = vec![0xFF, 0xFE];
+            data.extend("Name\nok\n".encode_utf16().flat_map(u16::to_le_bytes));
+            data.extend([0x00, 0xD8]);
+            data.extend("\n".encode_utf16().flat_map(u16::to_le_bytes));
+            Box::new(Cursor::new(data))
+        };
+        let err = CsvSource::from_reader(data(), &Default::default())
+            .unwrap()
+            .find_map(Result::err)
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("line 3 (byte 8): bytes that are not valid UTF-16LE"));
+
+        let opts = CsvReaderOpts {
+            lossy: true,
+            ..Default::default()
+        };
+        let mut src = CsvSource::from_reader(data(), &opts).unwrap();
+        assert_eq!(src.by_ref().count(), 2);
+        assert_eq!(
+            (src.encoding, src.replaced.as_slice()),
+            ("UTF-16LE", &[3][..])
+        );
+    }
+
+    #[test]
+    fn test_lossy_reports_lines() {
+        let data = || Box::new(Cursor::new(b"Name\nok\nJos\xe9\nok\n".to_vec()));
+        let mut src = CsvSource::from_reader(data(), &Default::default()).unwrap();
+        let err = src.nth(1).unwrap().unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("set --encoding (e.g. windows-1252) or use --lossy"));
+
+        let opts = CsvReaderOpts {
+            lossy: true,
+            ..Default::default()
+        };
+        let mut src = CsvSource::from_reader(data(), &opts).unwrap();
+        let records = src.by_ref().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(&records[1][0], "Jos\u{FFFD}");
+        assert_eq!(src.replaced, [3]);
+
+        // only the first lines are kept for the warning, the rest are counted
+        let many = [&b"Name\n"[..], &b"Jos\xe9\n".repeat(100)].concat();
+        let mut src = CsvSource::from_reader(Box::new(Cursor::new(many)), &opts).unwrap();
+        assert_eq!(src.by_ref().count(), 100);
+        assert_eq!(src.replaced.len(), MAX_REPORTED_LINES);
+        assert_eq!(src.replaced_lines, 100);
+
+        let opts = CsvReaderOpts {
+            encoding: Some(encoding_rs::UTF_8),
+            ..Default::default()
+        };
+        let err = CsvSource::from_reader(data(), &opts)
+            .unwrap()
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 3 (byte 8): bytes that are not valid UTF-8, set --encoding (e.g. windows-1252) or use --lossy"
+        );
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let data = "Name,Kit\nDennis,17\nGlenn,92,extra\nJennie,13\n";
+        let err = source(data, &Default::default())
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 3 (byte 19): expected 2 fields, found 3"
+        );
+
+        let rejects = tempfile::NamedTempFile::new().unwrap();
+        let opts = CsvReaderOpts {
+            on_error: OnError::Collect,
+            rejects: rejects.path().to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let mut src = source(data, &opts);
+        let names = src
+            .by_ref()
+            .map(|r| r.unwrap()[0].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Dennis", "Jennie"]);
+        assert_eq!(src.rejected(), 1);
+        drop(src);
+        assert_eq!(
+            std::fs::read_to_string(rejects.path()).unwrap(),
+            "file,line,byte,reason,record\n-,3,19,\"expected 2 fields, found 3\",\"Glenn,92,extra\"\n"
+        );
+    }
+
+    #[test]
+    fn test_shared_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str, data: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, data).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let inputs = [
+            path("a.csv", "Name,Kit\nDennis,17,x\nGlenn,92\n"),
+            path("b.csv", "Name,Kit\nJennie\nRhoda,3\n"),
+        ];
+        let opts = CsvReaderOpts {
+            on_error: OnError::Collect,
+            rejects: path("rejects.csv", ""),
+            ..Default::default()
+        };
+        let rejects = Rejects::open(&opts).unwrap();
+        let sources = inputs
+            .iter()
+            .map(|input| CsvSource::open_with_rejects(input, &opts, rejects.as_ref()).unwrap())
+            .collect::<Vec<_>>();
+        let rows = sources.into_iter().flatten().count();
+        assert_eq!(rows, 2);
+        drop(rejects);
+        let expected = format!(
+            "file,line,byte,reason,record\n\
+             {},2,9,\"expected 2 fields, found 3\",\"Dennis,17,x\"\n\
+             {},2,9,\"expected 2 fields, found 1\",Jennie\n",
+            inputs[0], inputs[1]
+        );
+        assert_eq!(std::fs::read_to_string(&opts.rejects).unwrap(), expected);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+use std::{collections::HashSet, io::Read};
+
+use super::csv_types::value_text;
+use crate::{get_reader, get_writer, CsvFlattenOpts, CsvOpts, OutputFormat};
+
+/// process_csv_reverse reads an array of objects in the `--from` format and writes it as csv.
+/// The headers are the union of the (flattened) keys of every object, in the order they first appear.
+pub fn process_csv_reverse(opts: &CsvOpts, from: OutputFormat, output: &str) -> Result<()> {
+    let [input] = opts.inputs.as_slice() else {
+        bail!("--from reads a single input");
+    };
+    let project = &opts.project;
+    if opts.filter.is_some()
+        || !project.select.is_empty()
+        || !project.exclude.is_empty()
+        || !project.rename.is_empty()
+        || !project.order.is_empty()
+    {
+        bail!("--where, --select, --exclude, --rename and --order do not apply to --from");
+    }
+    let mut reader = get_reader(input)?;
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let rows = parse_rows(&content, from)?
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            flatten_row(row, &opts.flatten).map_err(|e| anyhow!("item {}: {}", i + 1, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let headers = merge_headers(&rows);
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(opts.reader.delimiter)
+        .from_writer(get_writer(output)?);
+    writer.write_record(&headers)?;
+    for row in &rows {
+        // missing keys become empty cells
+        writer.write_record(
+            headers
+                .iter()
+                .map(|h| row.get(h).map(value_text).unwrap_or_default()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// parse the content to a list of objects, TOML has no top-level arrays so the rows are taken from
+/// the `rows` array of tables written by `rcli csv -f toml`, or from the only array in the document.
+fn parse_rows(content: &str, from: OutputFormat) -> Result<Vec<Map<String, Value>>> {
+    let doc: Value = match from {
+        OutputFormat::Json => serde_json::from_str(content)?,
+        OutputFormat::Ndjson => Value::Array(
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?,
+        ),
+        OutputFormat::Yaml => serde_yaml::from_str(content)?,
+        OutputFormat::Toml => {
+            let mut table = toml::from_str::<toml::Table>(content)?;
+            let rows = match table.remove("rows") {
+                Some(rows) => rows,
+                None => match (table.len(), table.into_iter().next()) {
+                    (1, Some((_, rows))) => rows,
+                    _ => bail!("TOML input must have a `rows` array of tables"),
+                },
+            };
+            toml_to_json(rows)
+        }
+        OutputFormat::Markdown | OutputFormat::Html => {
+            bail!("--from cannot read {} tables back", from)
+        }
+    };
+
+    let Value::Array(items) = doc else {
+        bail!("input must be an array of objects");
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| match item {
+            Value::Object(map) => Ok(map),
+            other => Err(anyhow!("item {} is not an object: {}", i + 1, other)),
+        })
+        .collect()
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(k, v)| (k, toml_to_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// flatten nested objects to `a.b` keys and arrays to `a[0]` keys,
+/// values deeper than `--max-depth` are kept as they are and written as JSON text.
+/// A key that is also the path of a nested value, like `"a.b"` next to `{"a": {"b": 1}}`, is an
+/// error rather than one value overwriting the other.
+fn flatten_row(row: &Map<String, Value>, opts: &CsvFlattenOpts) -> Result<Map<String, Value>> {
+    let mut ret = Map::new();
+    for (key, value) in row {
+        flatten_value(key.clone(), value, 1, opts, &mut ret)?;
+    }
+    Ok(ret)
+}
+
+fn flatten_value(
+    path: String,
+    value: &Value,
+    depth: usize,
+    opts: &CsvFlattenOpts,
+    ret: &mut Map<String, Value>,
+) -> Result<()> {
+    let can_descend = opts.max_depth.is_none_or(|max| depth < max);
+    match value {
+        Value::Object(map) if can_descend && !map.is_empty() => {
+            for (key, value) in map {
+                let path = format!("{}{}{}", path, opts.separator, key);
+                flatten_value(path, value, depth + 1, opts, ret)?;
+            }
+        }
+        Value::Array(items) if can_descend && !items.is_empty() => {
+            for (i, value) in items.iter().enumerate() {
+                flatten_value(format!("{}[{}]", path, i), value, depth + 1, opts, ret)?;
+            }
+        }
+        _ => {
+            if ret.contains_key(&path) {
+                bail!(
+                    "column {:?} is both a key and a flattened path, set another --separator",
+                    path
+                );
+            }
+            ret.insert(path, value.clone());
+        }
+    }
+    Ok(())
+}
+
+/// merge the keys of all rows, a key first seen in a later row is placed right after
+/// the key that precedes it in that row, so `{a, c}` and `{a, b, c}` give `a, b, c`
+fn merge_headers(rows: &[Map<String, Value>]) -> Vec<String> {
+    let mut headers: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    for row in rows {
+        let mut prev: Option<&String> = None;
+        for key in row.keys() {
+            if seen.insert(key.clone()) {
+                let pos = match prev {
+                    Some(prev) => headers.iter().position(|h| h == prev).map_or(0, |p| p + 1),
+                    None => 0,
+                };
+                headers.insert(pos, key.clone());
+            }
+            prev = Some(key);
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_merge_headers_keeps_order() {
+        let rows = [
+            object(json!({"a": 1, "c": 3})),
+            object(json!({"a": 1, "b": 2, "c": 3, "d": 4})),
+            object(json!({"e": 5, "a": 1})),
+        ];
+        assert_eq!(merge_headers(&rows), ["e", "a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_flatten_row() {
+        let row = object(json!({
+            "name": "Dennis",
+            "address": {"city": "Rosario", "geo": {"lat": 1.5}},
+            "tags": ["a", "b"],
+            "empty": {}
+        }));
+        let flat = flatten_row(&row, &CsvFlattenOpts::default()).unwrap();
+        let keys = flat.keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "name",
+                "address.city",
+                "address.geo.lat",
+                "tags[0]",
+                "tags[1]",
+                "empty"
+            ]
+        );
+
+        let opts = CsvFlattenOpts {
+            separator: "/".into(),
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let flat = flatten_row(&row, &opts).unwrap();
+        assert_eq!(flat["address/geo"], json!({"lat": 1.5}));
+        assert_eq!(value_text(&flat["address/geo"]), r#"{"lat":1.5}"#);
+
+        let row = object(json!({"a.b": 1, "a": {"b": 2}}));
+        assert!(flatten_row(&row, &CsvFlattenOpts::default()).is_err());
+        assert!(flatten_row(&row, &opts).is_ok());
+    }
+
+    #[test]
+    fn test_parse_toml_rows() {
+        let content = "[[rows]]\nName = \"a\"\n\"Kit Number\" = 17\n";
+        let rows = parse_rows(content, OutputFormat::Toml).unwrap();
+        assert_eq!(rows[0]["Kit Number"], json!(17));
+        assert!(parse_rows("{\"a\": 1}", OutputFormat::Json).is_err());
+    }
+}
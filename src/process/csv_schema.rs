@@ -0,0 +1,330 @@
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::{collections::HashSet, fmt::Write as _, io::Write, path::Path};
+
+use super::csv_reader::CsvSource;
+use super::csv_types::{infer_types, TypeConflict, TypeConverter};
+use super::csv_validate::{ColumnSchema, Schema};
+use crate::{get_writer, ColumnType, CsvSchemaOpts, SchemaFormat};
+
+/// Rust keywords, a field named after one is written as a raw identifier
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// the inferred type of a column and whether it has empty cells
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    ty: ColumnType,
+    nullable: bool,
+    /// every bool cell is `true` or `false`, the only spellings a `bool` field deserializes from
+    bool_literals: bool,
+}
+
+/// process_csv_schema infers the type and nullability of every column and writes them as a serde
+/// struct, a JSON Schema of the rows `rcli csv` writes, or the YAML schema of `rcli csv validate`.
+/// The types are inferred from the sample like the other commands, then every row is read: a
+/// column with a cell that does not fit its type becomes a string column, and a column with an
+/// empty cell is nullable.
+pub fn process_csv_schema(opts: &CsvSchemaOpts) -> Result<Vec<TypeConflict>> {
+    let mut source = CsvSource::open(&opts.input, &opts.reader)?;
+    let headers = source.headers().to_vec();
+    let sample = source
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let types = infer_types(&headers, &sample, &opts.types)?;
+    let mut converter = TypeConverter::from_opts(&headers, types.clone(), &opts.types);
+
+    let mut nullable = vec![false; headers.len()];
+    let mut bool_literals = vec![true; headers.len()];
+    for record in sample.into_iter().map(Ok).chain(source) {
+        let record = record?;
+        for idx in 0..headers.len() {
+            match converter.convert_cell(&headers, &record, idx) {
+                Value::Null => nullable[idx] = true,
+                Value::Bool(_) if !matches!(record.get(idx), Some("true" | "false")) => {
+                    bool_literals[idx] = false
+                }
+                _ => {}
+            }
+        }
+    }
+    let conflicts = converter.conflicts();
+    let columns = headers
+        .iter()
+        .zip(types)
+        .zip(nullable)
+        .zip(bool_literals)
+        .map(|(((name, ty), nullable), bool_literals)| Column {
+            name: name.clone(),
+            ty: if conflicts.iter().any(|c| &c.column == name) {
+                ColumnType::String
+            } else {
+                ty
+            },
+            nullable,
+            bool_literals,
+        })
+        .collect::<Vec<_>>();
+
+    let name = opts.name.clone().unwrap_or_else(|| {
+        let stem = Path::new(&opts.input)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .filter(|_| opts.input != "-")
+            .unwrap_or("record");
+        pascal_case(stem)
+    });
+    let content = match opts.emit {
+        SchemaFormat::Rust => rust_struct(&name, &columns),
+        SchemaFormat::JsonSchema => {
+            let timestamps_are_rfc3339 = opts.types.timezone.is_some();
+            let schema = json_schema(&name, &columns, timestamps_are_rfc3339);
+            serde_json::to_string_pretty(&schema)? + "\n"
+        }
+        SchemaFormat::Yaml => serde_yaml::to_string(&yaml_schema(&columns))?,
+    };
+    let mut writer = get_writer(&opts.output)?;
+    writer.write_all(content.as_bytes())?;
+    writer.flush()?;
+    Ok(conflicts)
+}
+
+/// a struct that deserializes the csv rows with the `csv` crate, a field whose name is not the
+/// header gets a `rename` attribute. A bool column with cells like `TRUE` is a `String` field,
+/// `bool` only deserializes from `true` and `false`.
+fn rust_struct(name: &str, columns: &[Column]) -> String {
+    let mut fields = HashSet::new();
+    let mut ret = String::from("use serde::{Deserialize, Serialize};\n\n");
+    ret.push_str("#[derive(Debug, Clone, Serialize, Deserialize)]\n");
+    let _ = writeln!(ret, "pub struct {} {{", name);
+    for column in columns {
+        let mut field = match snake_case(&column.name) {
+            f if matches!(f.as_str(), "self" | "super" | "crate") => format!("{}_", f),
+            f => f,
+        };
+        // two headers may give the same field name, e.g. `Kit Number` and `kit_number`
+        let base = field.clone();
+        let mut n = 1;
+        while !fields.insert(field.clone()) {
+            n += 1;
+            field = format!("{}_{}", base, n);
+        }
+        if field != column.name {
+            let _ = writeln!(ret, "    #[serde(rename = {:?})]", column.name);
+        }
+        let ty = match column.ty {
+            ColumnType::Bool if column.bool_literals => "bool",
+            ColumnType::Int => "i64",
+            ColumnType::Float => "f64",
+            ColumnType::Null
+            | ColumnType::Bool
+            | ColumnType::Date
+            | ColumnType::Timestamp
+            | ColumnType::String => "String",
+        };
+        let ty = if column.nullable {
+            format!("Option<{}>", ty)
+        } else {
+            ty.to_string()
+        };
+        // serde names a raw identifier without its `r#`
+        if KEYWORDS.contains(&field.as_str()) {
+            field = format!("r#{}", field);
+        }
+        let _ = writeln!(ret, "    pub {}: {},", field, ty);
+    }
+    ret.push_str("}\n");
+    ret
+}
+
+/// a JSON Schema of the rows as `rcli csv` converts them, every column is there and an empty
+/// cell is null. Timestamps without an offset are not RFC 3339, they only get the `date-time`
+/// format when `--timezone` gives them one.
+fn json_schema(name: &str, columns: &[Column], timestamps_are_rfc3339: bool) -> Value {
+    let mut properties = Map::new();
+    for column in columns {
+        let (ty, format) = match column.ty {
+            ColumnType::Bool => ("boolean", None),
+            ColumnType::Int => ("integer", None),
+            ColumnType::Float => ("number", None),
+            ColumnType::Date => ("string", Some("date")),
+            ColumnType::Timestamp if timestamps_are_rfc3339 => ("string", Some("date-time")),
+            ColumnType::Null | ColumnType::Timestamp | ColumnType::String => ("string", None),
+        };
+        let mut property = Map::new();
+        property.insert(
+            "type".into(),
+            if column.nullable {
+                json!([ty, "null"])
+            } else {
+                json!(ty)
+            },
+        );
+        if let Some(format) = format {
+            property.insert("format".into(), json!(format));
+        }
+        properties.insert(column.name.clone(), Value::Object(property));
+    }
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": name,
+        "type": "object",
+        "properties": properties,
+        "required": columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+        "additionalProperties": false,
+    })
+}
+
+/// the schema of `rcli csv validate`, a column without empty cells is required
+fn yaml_schema(columns: &[Column]) -> Schema {
+    let columns = columns
+        .iter()
+        .map(|column| ColumnSchema {
+            name: column.name.clone(),
+            ty: column.ty,
+            required: !column.nullable,
+            pattern: None,
+            values: None,
+            min: None,
+            max: None,
+            min_length: None,
+            max_length: None,
+        })
+        .collect();
+    Schema {
+        columns,
+        allow_extra: false,
+    }
+}
+
+/// `kit_number` for `Kit Number`, `first_name` for `firstName`, and `http_code` for `HTTPCode`
+fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut ret = String::with_capacity(name.len());
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !ret.is_empty() && !ret.ends_with('_') {
+                ret.push('_');
+            }
+            continue;
+        }
+        if c.is_uppercase() && i > 0 && !ret.ends_with('_') {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower) {
+                ret.push('_');
+            }
+        }
+        ret.extend(c.to_lowercase());
+    }
+    let ret = ret.trim_end_matches('_');
+    match ret.chars().next() {
+        None => "field".into(),
+        Some(c) if c.is_numeric() => format!("_{}", ret),
+        Some(_) => ret.to_string(),
+    }
+}
+
+/// `PlayerStats` for `player-stats`
+fn pascal_case(name: &str) -> String {
+    let ret = snake_case(name)
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<String>();
+    if ret.starts_with(|c: char| c.is_numeric()) {
+        format!("Record{}", ret)
+    } else {
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> Vec<Column> {
+        let column = |name: &str, ty, nullable| Column {
+            name: name.into(),
+            ty,
+            nullable,
+            bool_literals: true,
+        };
+        vec![
+            column("Name", ColumnType::String, false),
+            column("DOB", ColumnType::Date, true),
+            column("Kit Number", ColumnType::Int, false),
+            column("type", ColumnType::Float, true),
+            column("kit_number", ColumnType::Bool, false),
+            column("self", ColumnType::String, false),
+            Column {
+                bool_literals: false,
+                ..column("Active", ColumnType::Bool, true)
+            },
+        ]
+    }
+
+    #[test]
+    fn test_rust_struct() {
+        assert_eq!(
+            rust_struct("Player", &columns()),
+            r#"use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Player {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "DOB")]
+    pub dob: Option<String>,
+    #[serde(rename = "Kit Number")]
+    pub kit_number: i64,
+    pub r#type: Option<f64>,
+    #[serde(rename = "kit_number")]
+    pub kit_number_2: bool,
+    #[serde(rename = "self")]
+    pub self_: String,
+    #[serde(rename = "Active")]
+    pub active: Option<String>,
+}
+"#
+        );
+        assert_eq!(snake_case("firstName"), "first_name");
+        assert_eq!(snake_case("HTTPCode (2xx)"), "http_code_2xx");
+        assert_eq!(snake_case("2024 Goals"), "_2024_goals");
+        assert_eq!(pascal_case("player-stats"), "PlayerStats");
+    }
+
+    #[test]
+    fn test_json_and_yaml_schema() {
+        let schema = json_schema("players", &columns(), false);
+        assert_eq!(
+            schema["properties"]["DOB"],
+            json!({"type": ["string", "null"], "format": "date"})
+        );
+        assert_eq!(
+            schema["properties"]["Kit Number"],
+            json!({"type": "integer"})
+        );
+        assert_eq!(schema["required"].as_array().unwrap().len(), 7);
+
+        // the YAML is read back by `rcli csv validate`
+        let yaml = serde_yaml::to_string(&yaml_schema(&columns())).unwrap();
+        assert!(yaml.starts_with("columns:\n- name: Name\n  type: string\n  required: true\n"));
+        let schema: Schema = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(schema.columns[1].ty, ColumnType::Date);
+        assert!(!schema.columns[1].required);
+        assert!(!schema.allow_extra);
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::{collections::VecDeque, io::Write};
+
+use super::csv_reader::CsvSource;
+use super::csv_table::Table;
+use super::csv_types::{infer_types, TypeConflict, TypeConverter};
+use crate::{get_writer, terminal_width, write_paged, CsvShowOpts};
+
+/// process_csv_show prints the csv file as a table that fits the terminal, tables longer than
+/// the screen are shown in a pager. Only the rows that are shown are converted.
+pub fn process_csv_show(opts: &CsvShowOpts) -> Result<Vec<TypeConflict>> {
+    let mut source = CsvSource::open(&opts.input, &opts.reader)?;
+    let headers = source.headers().to_vec();
+    let sample = source
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let types = infer_types(&headers, &sample, &opts.types)?;
+    let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+    let records = select_rows(
+        sample.into_iter().map(Ok).chain(source),
+        opts.head,
+        opts.tail,
+    )?;
+
+    let mut table = Table::new(headers.clone()).max_cell_width(opts.max_width);
+    if let Some(width) = opts.width.or_else(terminal_width) {
+        table = table.max_width(width);
+    }
+    for record in &records {
+        table.push_row(&converter.convert(&headers, record));
+    }
+    let mut content = Vec::new();
+    table.render(&mut content)?;
+    let content = String::from_utf8(content)?;
+
+    if opts.no_pager {
+        let mut writer = get_writer("-")?;
+        writer.write_all(content.as_bytes())?;
+        writer.flush()?;
+    } else {
+        write_paged(&content)?;
+    }
+    Ok(converter.conflicts())
+}
+
+/// keep the first `head` or the last `tail` rows, `--tail` only holds `tail` rows in memory
+fn select_rows<T>(
+    rows: impl Iterator<Item = Result<T>>,
+    head: Option<usize>,
+    tail: Option<usize>,
+) -> Result<Vec<T>> {
+    if let Some(head) = head {
+        return rows.take(head).collect();
+    }
+    let Some(tail) = tail else {
+        return rows.collect();
+    };
+    let mut ret = VecDeque::with_capacity(tail);
+    for row in rows {
+        let row = row?;
+        if tail == 0 {
+            continue;
+        }
+        if ret.len() == tail {
+            ret.pop_front();
+        }
+        ret.push_back(row);
+    }
+    Ok(ret.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_rows() {
+        let rows = || (1..=5).map(Ok);
+        assert_eq!(select_rows(rows(), Some(2), None).unwrap(), [1, 2]);
+        assert_eq!(select_rows(rows(), None, Some(2)).unwrap(), [4, 5]);
+        assert_eq!(select_rows(rows(), None, Some(9)).unwrap(), [1, 2, 3, 4, 5]);
+        assert!(select_rows(rows(), None, Some(0)).unwrap().is_empty());
+        assert_eq!(select_rows(rows(), None, None).unwrap().len(), 5);
+    }
+}
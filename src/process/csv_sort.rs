@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+};
+
+use super::csv_reader::CsvSource;
+use super::csv_types::{infer_types, sort_order, TypeConflict, TypeConverter};
+use super::csv_writer::{create_csv_writer, create_row_writer};
+use crate::{get_writer, CsvDedupOpts, CsvSortOpts, CsvTypeOpts, OutputFormat, SortOrder};
+
+/// once there are this many runs they are merged into one, so a sort never holds more files open
+const MERGE_WIDTH: usize = 64;
+
+/// a row of the input and its sort key, `index` is the position of the row in the input so rows
+/// with equal keys keep their input order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub key: Vec<Value>,
+    pub index: u64,
+    pub row: Vec<Value>,
+}
+
+/// ExternalSort sorts entries in memory until `capacity` of them are buffered, then writes the
+/// sorted buffer to a temporary file as a run. The runs are merged when the entries are read back.
+struct ExternalSort {
+    buffer: Vec<Entry>,
+    capacity: usize,
+    order: Vec<SortOrder>,
+    runs: Vec<File>,
+}
+
+/// Merge reads sorted runs back in order, always taking the smallest head
+pub struct Merge {
+    runs: Vec<Run>,
+    heads: Vec<Option<Entry>>,
+    order: Vec<SortOrder>,
+}
+
+type Run = Box<dyn Iterator<Item = Result<Entry>>>;
+
+/// process_csv_sort sorts the rows by the `--by` columns, cells compare by their column type so
+/// `9` comes before `10`. Inputs with more than `--buffer-rows` rows are sorted on disk.
+pub fn process_csv_sort(opts: &CsvSortOpts) -> Result<Vec<TypeConflict>> {
+    let columns = opts.by.iter().map(|(c, _)| c.clone()).collect::<Vec<_>>();
+    let order = opts.by.iter().map(|(_, o)| *o).collect();
+    let source = CsvSource::open(&opts.input, &opts.reader)?;
+    let (headers, rows, conflicts) = sort_source(
+        source,
+        &opts.types,
+        &columns,
+        order,
+        opts.format.is_some(),
+        opts.buffer_rows,
+    )?;
+    write_rows(
+        &opts.output,
+        opts.format,
+        opts.reader.delimiter,
+        &headers,
+        rows,
+    )?;
+    Ok(conflicts)
+}
+
+/// process_csv_dedup keeps the first row of every key and writes the rows in input order, it
+/// returns the number of rows that were removed. Like `process_csv_sort` it works on disk for
+/// inputs with more than `--buffer-rows` rows.
+pub fn process_csv_dedup(opts: &CsvDedupOpts) -> Result<(usize, Vec<TypeConflict>)> {
+    let source = CsvSource::open(&opts.input, &opts.reader)?;
+    let (headers, rows, conflicts) = sort_source(
+        source,
+        &opts.types,
+        &opts.key,
+        Vec::new(),
+        opts.format.is_some(),
+        opts.buffer_rows,
+    )?;
+    let (rows, removed) = dedup(rows, opts.buffer_rows)?;
+    write_rows(
+        &opts.output,
+        opts.format,
+        opts.reader.delimiter,
+        &headers,
+        rows,
+    )?;
+    Ok((removed, conflicts))
+}
+
+/// sort the rows of the source by the columns, every column is ascending unless `order` says
+/// otherwise and no columns means the whole row. Typed rows are converted, otherwise the cells are
+/// kept as they are and only the key is typed.
+pub fn sort_source(
+    mut source: CsvSource,
+    types: &CsvTypeOpts,
+    columns: &[String],
+    order: Vec<SortOrder>,
+    typed: bool,
+    buffer_rows: usize,
+) -> Result<(Vec<String>, Merge, Vec<TypeConflict>)> {
+    let headers = source.headers().to_vec();
+    let key = if columns.is_empty() {
+        (0..headers.len()).collect()
+    } else {
+        columns
+            .iter()
+            .map(|c| {
+                headers
+                    .iter()
+                    .position(|h| h == c)
+                    .ok_or_else(|| anyhow!("column {:?} is not in the input", c))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    let mut order = order;
+    order.resize(key.len(), SortOrder::Asc);
+
+    let sample = source
+        .by_ref()
+        .take(types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let mut converter =
+        TypeConverter::from_opts(&headers, infer_types(&headers, &sample, types)?, types);
+    let mut sort = ExternalSort::new(buffer_rows, order);
+    for (index, record) in sample.into_iter().map(Ok).chain(source).enumerate() {
+        let record = record?;
+        let (row, key) = if typed {
+            let row = converter.convert(&headers, &record);
+            let key = key.iter().map(|i| row[*i].clone()).collect();
+            (row, key)
+        } else {
+            let row = record.iter().map(Value::from).collect();
+            let key = key
+                .iter()
+                .map(|i| converter.convert_cell(&headers, &record, *i))
+                .collect();
+            (row, key)
+        };
+        sort.push(Entry {
+            key,
+            index: index as u64,
+            row,
+        })?;
+    }
+    Ok((headers, sort.finish()?, converter.conflicts()))
+}
+
+/// drop the rows whose key sorts equal to the previous one, the rows are sorted by key so every
+/// duplicate follows the first row of its key. The rows that are kept are sorted back into input
+/// order.
+fn dedup(rows: Merge, buffer_rows: usize) -> Result<(Merge, usize)> {
+    let mut kept = ExternalSort::new(buffer_rows, Vec::new());
+    let mut removed = 0;
+    let mut last: Option<Vec<Value>> = None;
+    for entry in rows {
+        let mut entry = entry?;
+        let duplicate = last.as_ref().is_some_and(|last| {
+            last.len() == entry.key.len()
+                && last
+                    .iter()
+                    .zip(&entry.key)
+                    .all(|(a, b)| sort_order(a, b) == Ordering::Equal)
+        });
+        if duplicate {
+            removed += 1;
+            continue;
+        }
+        last = Some(std::mem::take(&mut entry.key));
+        kept.push(entry)?;
+    }
+    Ok((kept.finish()?, removed))
+}
+
+fn write_rows(
+    output: &str,
+    format: Option<OutputFormat>,
+    delimiter: u8,
+    headers: &[String],
+    rows: impl Iterator<Item = Result<Entry>>,
+) -> Result<()> {
+    let writer = get_writer(output)?;
+    let mut writer = match format {
+        Some(format) => create_row_writer(format, writer, headers),
+        None => create_csv_writer(writer, delimiter, headers)?,
+    };
+    for entry in rows {
+        let row = headers
+            .iter()
+            .cloned()
+            .zip(entry?.row)
+            .collect::<Map<_, _>>();
+        writer.write_row(&row)?;
+    }
+    writer.finish()
+}
+
+/// compare the keys column by column, nulls sort first in ascending order
+fn compare(order: &[SortOrder], a: &Entry, b: &Entry) -> Ordering {
+    a.key
+        .iter()
+        .zip(&b.key)
+        .zip(order)
+        .map(|((x, y), order)| match order {
+            SortOrder::Asc => sort_order(x, y),
+            SortOrder::Desc => sort_order(y, x),
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or_else(|| a.index.cmp(&b.index))
+}
+
+impl ExternalSort {
+    fn new(capacity: usize, order: Vec<SortOrder>) -> Self {
+        Self {
+            buffer: Vec::new(),
+            capacity: capacity.max(1),
+            order,
+            runs: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, entry: Entry) -> Result<()> {
+        self.buffer.push(entry);
+        if self.buffer.len() >= self.capacity {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.sort_by(|a, b| compare(&self.order, a, b));
+        self.runs.push(write_run(buffer.into_iter().map(Ok))?);
+        if self.runs.len() >= MERGE_WIDTH {
+            let runs = std::mem::take(&mut self.runs)
+                .into_iter()
+                .map(read_run)
+                .collect();
+            let merged = Merge::new(runs, self.order.clone())?;
+            self.runs.push(write_run(merged)?);
+        }
+        Ok(())
+    }
+
+    /// the entries in order, the last partial buffer is merged from memory
+    fn finish(mut self) -> Result<Merge> {
+        self.buffer.sort_by(|a, b| compare(&self.order, a, b));
+        let mut runs = self.runs.into_iter().map(read_run).collect::<Vec<_>>();
+        runs.push(Box::new(self.buffer.into_iter().map(Ok)));
+        Merge::new(runs, self.order)
+    }
+}
+
+/// write the entries to a temporary file as JSON lines, the file is deleted once it is dropped
+fn write_run(entries: impl Iterator<Item = Result<Entry>>) -> Result<File> {
+    let mut writer = BufWriter::new(tempfile::tempfile()?);
+    for entry in entries {
+        serde_json::to_writer(&mut writer, &entry?)?;
+        writer.write_all(b"\n")?;
+    }
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+fn read_run(file: File) -> Run {
+    Box::new(
+        BufReader::new(file)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?)),
+    )
+}
+
+impl Merge {
+    fn new(mut runs: Vec<Run>, order: Vec<SortOrder>) -> Result<Self> {
+        let heads = runs
+            .iter_mut()
+            .map(|run| run.next().transpose())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { runs, heads, order })
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // there are at most MERGE_WIDTH runs, a linear scan of the heads is cheap enough
+        let (i, _) = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((i, head.as_ref()?)))
+            .min_by(|(_, a), (_, b)| compare(&self.order, a, b))?;
+        let next = match self.runs[i].next().transpose() {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        std::mem::replace(&mut self.heads[i], next).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CsvReaderOpts;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn sorted(content: &str, columns: &[&str], order: Vec<SortOrder>, buffer: usize) -> Merge {
+        let reader = Box::new(Cursor::new(content.to_string()));
+        let source = CsvSource::from_reader(reader, &CsvReaderOpts::default()).unwrap();
+        let columns = columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let types = CsvTypeOpts::default();
+        sort_source(source, &types, &columns, order, true, buffer)
+            .unwrap()
+            .1
+    }
+
+    fn rows(rows: Merge) -> Vec<Value> {
+        rows.map(|e| Value::Array(e.unwrap().row)).collect()
+    }
+
+    #[test]
+    fn test_sort_and_dedup_rows() {
+        let content = "Name,Kit\nb,10\na,9\nc,\nb,2\nd,10\n";
+        let by_kit = sorted(content, &["Kit", "Name"], vec![SortOrder::Desc], 2);
+        assert_eq!(
+            rows(by_kit),
+            [
+                json!(["b", 10]),
+                json!(["d", 10]),
+                json!(["a", 9]),
+                json!(["b", 2]),
+                json!(["c", null]),
+            ]
+        );
+
+        let (kept, removed) = dedup(sorted(content, &["Name"], vec![], 2), 2).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(
+            rows(kept),
+            [
+                json!(["b", 10]),
+                json!(["a", 9]),
+                json!(["c", null]),
+                json!(["d", 10])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup_keeps_distinct_ids_and_codes() {
+        let content = "Id,Zip\n1234567890123456789,02134\n1234567890123456788,2134\n\
+                       1234567890123456789,02134\n";
+        let (kept, removed) = dedup(sorted(content, &["Id"], vec![], 2), 2).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(
+            rows(kept),
+            [
+                json!([1234567890123456789_i64, "02134"]),
+                json!([1234567890123456788_i64, "2134"])
+            ]
+        );
+
+        let content = "Zip\n02134\n2134\n02134\n";
+        let (kept, removed) = dedup(sorted(content, &["Zip"], vec![], 2), 2).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(rows(kept), [json!(["02134"]), json!(["2134"])]);
+    }
+
+    #[test]
+    fn test_external_sort_merges_runs() {
+        let mut sort = ExternalSort::new(3, vec![SortOrder::Asc]);
+        let keys = (0..500u64).map(|i| (i * 7919) % 101).collect::<Vec<_>>();
+        for (index, key) in keys.iter().enumerate() {
+            let entry = Entry {
+                key: vec![json!(key)],
+                index: index as u64,
+                row: vec![json!(index)],
+            };
+            sort.push(entry).unwrap();
+        }
+        // 166 runs of 3 are more than MERGE_WIDTH, so some were merged on the way
+        assert!(sort.runs.len() < MERGE_WIDTH);
+
+        let mut expected = keys.iter().enumerate().collect::<Vec<_>>();
+        expected.sort_by_key(|(index, key)| (**key, *index));
+        let merged = sort
+            .finish()
+            .unwrap()
+            .map(|e| e.unwrap().index as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            expected.into_iter().map(|(i, _)| i).collect::<Vec<_>>()
+        );
+    }
+}
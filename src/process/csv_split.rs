@@ -0,0 +1,238 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use std::{collections::HashSet, fs, path::Path};
+
+use super::csv_reader::CsvSource;
+use super::csv_sort::sort_source;
+use super::csv_types::{infer_types, value_key, value_text, TypeConflict, TypeConverter};
+use super::csv_writer::{create_csv_writer, create_row_writer, RowWriter};
+use crate::{get_writer, CsvSplitOpts};
+
+/// Parts writes the rows to one file at a time, a part is finished before the next one starts
+struct Parts<'a> {
+    opts: &'a CsvSplitOpts,
+    headers: &'a [String],
+    stem: String,
+    current: Option<Box<dyn RowWriter>>,
+    // every file written so far, parts that end up with the same name get a numeric suffix
+    files: HashSet<String>,
+}
+
+/// process_csv_split writes the rows to parts of `--rows` rows, or to a part per value of the
+/// `--by` column, every part has the header row. `--by` groups the rows with the on-disk sort of
+/// `rcli csv sort`, so only one part is open at a time however many groups there are. It returns
+/// the number of parts written.
+pub fn process_csv_split(opts: &CsvSplitOpts) -> Result<(usize, Vec<TypeConflict>)> {
+    if !opts.pattern.contains("{part}") {
+        bail!("--pattern must contain {{part}}, or every part would be written to the same file");
+    }
+    let source = CsvSource::open(&opts.input, &opts.reader)?;
+    let headers = source.headers().to_vec();
+    let mut parts = Parts::new(opts, &headers);
+    let typed = opts.format.is_some();
+
+    let conflicts = match &opts.by {
+        Some(column) => {
+            let columns = std::slice::from_ref(column);
+            let (_, rows, conflicts) = sort_source(
+                source,
+                &opts.types,
+                columns,
+                Vec::new(),
+                typed,
+                opts.buffer_rows,
+            )?;
+            // the rows of a group are next to each other and still in input order
+            let mut last = None;
+            for entry in rows {
+                let entry = entry?;
+                let key = value_key(&entry.key[0]);
+                if last.as_ref() != Some(&key) {
+                    parts.start(&value_text(&entry.key[0]))?;
+                    last = Some(key);
+                }
+                parts.write(entry.row)?;
+            }
+            conflicts
+        }
+        None => split_rows(source, opts, &mut parts)?,
+    };
+    Ok((parts.finish()?, conflicts))
+}
+
+/// start a new part every `--rows` rows, parts are numbered from 1
+fn split_rows(
+    mut source: CsvSource,
+    opts: &CsvSplitOpts,
+    parts: &mut Parts,
+) -> Result<Vec<TypeConflict>> {
+    let headers = source.headers().to_vec();
+    let size = opts.rows.unwrap_or(u64::MAX);
+    let sample = source
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let types = infer_types(&headers, &sample, &opts.types)?;
+    let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+    for (i, record) in sample.into_iter().map(Ok).chain(source).enumerate() {
+        let record = record?;
+        let i = i as u64;
+        if i.is_multiple_of(size) {
+            parts.start(&(i / size + 1).to_string())?;
+        }
+        let row = if opts.format.is_some() {
+            converter.convert(&headers, &record)
+        } else {
+            record.iter().map(Value::from).collect()
+        };
+        parts.write(row)?;
+    }
+    Ok(converter.conflicts())
+}
+
+impl<'a> Parts<'a> {
+    fn new(opts: &'a CsvSplitOpts, headers: &'a [String]) -> Self {
+        let stem = Path::new(&opts.input)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .filter(|_| opts.input != "-")
+            .unwrap_or("stdin")
+            .to_string();
+        Self {
+            opts,
+            headers,
+            stem,
+            current: None,
+            files: HashSet::new(),
+        }
+    }
+
+    fn start(&mut self, part: &str) -> Result<()> {
+        if let Some(writer) = self.current.take() {
+            writer.finish()?;
+        }
+        let ext = self.opts.format.map_or("csv", Into::into);
+        let dir = Path::new(&self.opts.output_dir);
+        fs::create_dir_all(dir)?;
+        // `a b` and `a_b` are both `a_b` in a file name, the second one is written to `a_b-2`
+        let base = file_part(part);
+        let mut suffixed = base.clone();
+        let mut n = 1;
+        let path = loop {
+            let name = self
+                .opts
+                .pattern
+                .replace("{stem}", &self.stem)
+                .replace("{part}", &suffixed)
+                .replace("{ext}", ext);
+            let path = dir.join(name).to_string_lossy().into_owned();
+            if self.files.insert(path.clone()) {
+                break path;
+            }
+            n += 1;
+            suffixed = format!("{}-{}", base, n);
+        };
+
+        let writer = get_writer(&path)?;
+        self.current = Some(match self.opts.format {
+            Some(format) => create_row_writer(format, writer, self.headers),
+            None => create_csv_writer(writer, self.opts.reader.delimiter, self.headers)?,
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, row: Vec<Value>) -> Result<()> {
+        let row = self.headers.iter().cloned().zip(row).collect::<Map<_, _>>();
+        match &mut self.current {
+            Some(writer) => writer.write_row(&row),
+            None => bail!("no part was started"),
+        }
+    }
+
+    /// finish the last part, returns the number of parts
+    fn finish(mut self) -> Result<usize> {
+        if let Some(writer) = self.current.take() {
+            writer.finish()?;
+        }
+        Ok(self.files.len())
+    }
+}
+
+/// the part as it goes in a file name, characters that are not safe in file names become `_`
+fn file_part(part: &str) -> String {
+    if part.is_empty() {
+        return "empty".into();
+    }
+    part.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn split(args: &[&str]) -> (tempfile::TempDir, Result<usize>) {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().to_str().unwrap();
+        let base = ["split", "-i", "assets/test.csv", "-o", out];
+        let opts = CsvSplitOpts::try_parse_from([&base[..], args].concat()).unwrap();
+        let ret = process_csv_split(&opts).map(|(parts, _)| parts);
+        (dir, ret)
+    }
+
+    #[test]
+    fn test_split_by_rows() {
+        let (dir, parts) = split(&["--rows", "40"]);
+        assert_eq!(parts.unwrap(), 3);
+        let last = fs::read_to_string(dir.path().join("test-3.csv")).unwrap();
+        assert!(last.starts_with("Name,Position,DOB,Nationality,Kit Number\n"));
+        assert_eq!(last.lines().count(), 100 - 80 + 1);
+    }
+
+    #[test]
+    fn test_split_by_column() {
+        let (dir, parts) = split(&["--by", "Nationality", "--buffer-rows", "7", "-f", "ndjson"]);
+        let parts = parts.unwrap();
+        let files = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, parts);
+        let ar = fs::read_to_string(dir.path().join("test-AR.ndjson")).unwrap();
+        assert!(ar.starts_with(r#"{"Name":"Dennis Harris","Position":"Detujgi""#));
+        assert!(ar
+            .lines()
+            .all(|line| line.contains(r#""Nationality":"AR""#)));
+
+        let (_, err) = split(&["--by", "Nationality", "--pattern", "all.csv"]);
+        assert!(err.is_err());
+
+        // distinct values with the same file name do not overwrite each other
+        let input = tempfile::NamedTempFile::new().unwrap();
+        fs::write(input.path(), "Id,Tag\n1,a b\n2,a_b\n3,a b\n").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (input, out) = (input.path().to_str().unwrap(), dir.path().to_str().unwrap());
+        let args = [
+            "split",
+            "-i",
+            input,
+            "-o",
+            out,
+            "--by",
+            "Tag",
+            "--pattern",
+            "{part}.csv",
+        ];
+        let (parts, _) = process_csv_split(&CsvSplitOpts::try_parse_from(args).unwrap()).unwrap();
+        assert_eq!(parts, 2);
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("a_b.csv"), "Id,Tag\n1,a b\n3,a b\n");
+        assert_eq!(read("a_b-2.csv"), "Id,Tag\n2,a_b\n");
+        assert_eq!(file_part("São Paulo/1.5"), "São_Paulo_1_5");
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::Result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use super::csv_reader::CsvSource;
+use super::csv_table::Table;
+use super::csv_types::{infer_types, sort_order, value_text, TypeConflict, TypeConverter};
+use super::csv_writer::create_row_writer;
+use crate::{get_writer, ColumnType, CsvStatsOpts};
+
+/// the number of HyperLogLog registers is `2^HLL_BITS`, 16K registers estimate within ~1%
+const HLL_BITS: u32 = 14;
+
+/// the median of a column with more numbers than this is the median of a random sample of them
+const MEDIAN_SAMPLE: usize = 1 << 16;
+
+/// ColumnStats is the profile of one column, one row of the `rcli csv stats` report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnStats {
+    pub column: String,
+    #[serde(rename = "type")]
+    pub ty: ColumnType,
+    pub count: usize,
+    /// empty cells
+    pub nulls: usize,
+    pub distinct: usize,
+    /// whether `distinct` and the `top` counts are estimates, because the column has more than
+    /// `--distinct-limit` values
+    pub approximate: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median: Option<f64>,
+    /// whether `median` is estimated from a sample, because the column has more numbers than fit
+    /// in memory
+    pub median_approximate: bool,
+    /// sample standard deviation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stddev: Option<f64>,
+    pub top: Vec<TopValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopValue {
+    pub value: String,
+    pub count: usize,
+}
+
+/// the running profile of a column
+struct Profile {
+    nulls: usize,
+    min: Value,
+    max: Value,
+    // Welford's running mean and sum of squared differences
+    count: usize,
+    mean: f64,
+    m2: f64,
+    // a reservoir sample of the numbers for the median, all of them up to MEDIAN_SAMPLE
+    numbers: Vec<f64>,
+    rng: StdRng,
+    counts: ValueCounts,
+}
+
+/// ValueCounts counts every distinct value until there are more than `limit`, from then on it is
+/// a Misra-Gries summary that keeps the frequent values (with their counts as lower bounds) in
+/// bounded memory, and the distinct count comes from a HyperLogLog sketch.
+struct ValueCounts {
+    counts: HashMap<String, usize>,
+    limit: usize,
+    overflowed: bool,
+    registers: Vec<u8>,
+}
+
+/// process_csv_stats reads the csv file like `rcli csv` does and profiles every column, the type
+/// conflicts are returned to the caller.
+pub fn process_csv_stats(opts: &CsvStatsOpts) -> Result<Vec<TypeConflict>> {
+    let (stats, conflicts) = profile(CsvSource::open(&opts.input, &opts.reader)?, opts)?;
+
+    let writer = get_writer(&opts.output)?;
+    match opts.format {
+        Some(format) => {
+            let mut writer = create_row_writer(format, writer, &[]);
+            for column in &stats {
+                if let Value::Object(row) = serde_json::to_value(column)? {
+                    writer.write_row(&row)?;
+                }
+            }
+            writer.finish()?;
+        }
+        None => {
+            let headers = [
+                "column", "type", "count", "nulls", "distinct", "min", "max", "mean", "median",
+                "stddev", "top",
+            ];
+            let mut table = Table::new(headers.map(String::from).to_vec());
+            for column in &stats {
+                table.push_row(&column.table_row());
+            }
+            let mut writer = writer;
+            table.render(&mut writer)?;
+        }
+    }
+    Ok(conflicts)
+}
+
+fn profile(
+    mut source: CsvSource,
+    opts: &CsvStatsOpts,
+) -> Result<(Vec<ColumnStats>, Vec<TypeConflict>)> {
+    let headers = source.headers().to_vec();
+    let sample = source
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    let types = infer_types(&headers, &sample, &opts.types)?;
+    let mut converter = TypeConverter::from_opts(&headers, types.clone(), &opts.types);
+
+    let mut profiles = headers
+        .iter()
+        .map(|_| Profile::new(opts.distinct_limit))
+        .collect::<Vec<_>>();
+    let mut count = 0;
+    for result in sample.into_iter().map(Ok).chain(source) {
+        let record = result?;
+        count += 1;
+        let values = converter.convert(&headers, &record);
+        for (profile, value) in profiles.iter_mut().zip(values) {
+            profile.add(value);
+        }
+    }
+
+    let stats = headers
+        .into_iter()
+        .zip(types)
+        .zip(profiles)
+        .map(|((column, ty), profile)| profile.finish(column, ty, count, opts.top))
+        .collect();
+    Ok((stats, converter.conflicts()))
+}
+
+impl Profile {
+    fn new(limit: usize) -> Self {
+        Self {
+            nulls: 0,
+            min: Value::Null,
+            max: Value::Null,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            numbers: Vec::new(),
+            // a fixed seed keeps the report the same from run to run
+            rng: StdRng::seed_from_u64(0),
+            counts: ValueCounts::new(limit),
+        }
+    }
+
+    fn add(&mut self, value: Value) {
+        if value.is_null() {
+            self.nulls += 1;
+            return;
+        }
+        // cells that conflict with a numeric column type are strings, they only count as values
+        if let Some(n) = value.as_f64() {
+            self.count += 1;
+            let delta = n - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (n - self.mean);
+            if self.numbers.len() < MEDIAN_SAMPLE {
+                self.numbers.push(n);
+            } else {
+                let i = self.rng.gen_range(0..self.count);
+                if i < MEDIAN_SAMPLE {
+                    self.numbers[i] = n;
+                }
+            }
+        }
+        if self.min.is_null() || sort_order(&value, &self.min) == Ordering::Less {
+            self.min = value.clone();
+        }
+        if self.max.is_null() || sort_order(&value, &self.max) == Ordering::Greater {
+            self.max = value.clone();
+        }
+        self.counts.add(value_text(&value));
+    }
+
+    fn finish(mut self, column: String, ty: ColumnType, count: usize, top: usize) -> ColumnStats {
+        let numeric = matches!(ty, ColumnType::Int | ColumnType::Float) && !self.numbers.is_empty();
+        let n = self.numbers.len();
+        let seen = self.count;
+        let (mean, median, stddev) = if numeric {
+            self.numbers.sort_by(f64::total_cmp);
+            let median = if n % 2 == 1 {
+                self.numbers[n / 2]
+            } else {
+                (self.numbers[n / 2 - 1] + self.numbers[n / 2]) / 2.0
+            };
+            let stddev = (seen > 1).then(|| (self.m2 / (seen - 1) as f64).sqrt());
+            (Some(self.mean), Some(median), stddev)
+        } else {
+            (None, None, None)
+        };
+        ColumnStats {
+            column,
+            ty,
+            count,
+            nulls: self.nulls,
+            distinct: self.counts.distinct(),
+            approximate: self.counts.overflowed,
+            min: Some(self.min).filter(|v| !v.is_null()),
+            max: Some(self.max).filter(|v| !v.is_null()),
+            mean,
+            median,
+            median_approximate: numeric && seen > n,
+            stddev,
+            top: self.counts.top(top),
+        }
+    }
+}
+
+impl ValueCounts {
+    fn new(limit: usize) -> Self {
+        Self {
+            counts: HashMap::new(),
+            limit,
+            overflowed: false,
+            registers: vec![0; 1 << HLL_BITS],
+        }
+    }
+
+    fn add(&mut self, value: String) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - HLL_BITS)) as usize;
+        let rank = ((hash << HLL_BITS).leading_zeros() + 1).min(64 - HLL_BITS + 1) as u8;
+        self.registers[register] = self.registers[register].max(rank);
+
+        if let Some(count) = self.counts.get_mut(&value) {
+            *count += 1;
+        } else if self.counts.len() < self.limit {
+            self.counts.insert(value, 1);
+        } else {
+            // the summary is full: decrement every count and drop the ones that reach zero
+            self.overflowed = true;
+            self.counts.retain(|_, count| {
+                *count -= 1;
+                *count > 0
+            });
+        }
+    }
+
+    fn distinct(&self) -> usize {
+        if !self.overflowed {
+            return self.counts.len();
+        }
+        let m = self.registers.len() as f64;
+        let sum = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-(*r as i32)))
+            .sum::<f64>();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // small cardinalities are estimated better by linear counting
+        let estimate = if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        };
+        estimate.round() as usize
+    }
+
+    /// the most frequent values, ties are ordered by value so the report is stable
+    fn top(&self, n: usize) -> Vec<TopValue> {
+        let mut counts = self.counts.iter().collect::<Vec<_>>();
+        counts.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+        counts
+            .into_iter()
+            .take(n)
+            .map(|(value, count)| TopValue {
+                value: value.clone(),
+                count: *count,
+            })
+            .collect()
+    }
+}
+
+impl ColumnStats {
+    /// the cells of the table report, floats are rounded and approximate values start with `~`
+    fn table_row(&self) -> Vec<Value> {
+        let round =
+            |f: Option<f64>| f.map_or(Value::Null, |f| Value::from((f * 1e4).round() / 1e4));
+        let approximate = |value: Value, approximate: bool| match value {
+            Value::Null => value,
+            _ if approximate => Value::from(format!("~{}", value)),
+            _ => value,
+        };
+        let distinct = approximate(Value::from(self.distinct), self.approximate);
+        let median = approximate(round(self.median), self.median_approximate);
+        let mark = if self.approximate { "~" } else { "" };
+        let top = self
+            .top
+            .iter()
+            .map(|t| format!("{} ({}{})", t.value, mark, t.count))
+            .collect::<Vec<_>>();
+        vec![
+            Value::from(self.column.as_str()),
+            Value::from(self.ty.to_string()),
+            Value::from(self.count),
+            Value::from(self.nulls),
+            distinct,
+            self.min.clone().unwrap_or_default(),
+            self.max.clone().unwrap_or_default(),
+            round(self.mean),
+            median,
+            round(self.stddev),
+            Value::from(top.join(", ")),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn stats(content: &str, args: &[&str]) -> Vec<ColumnStats> {
+        let opts = CsvStatsOpts::try_parse_from([&["stats"], args].concat()).unwrap();
+        let reader = Box::new(Cursor::new(content.to_string()));
+        let source = CsvSource::from_reader(reader, &opts.reader).unwrap();
+        profile(source, &opts).unwrap().0
+    }
+
+    #[test]
+    fn test_column_stats() {
+        let content = "Name,Kit Number,Nationality\na,17,AR\nb,2,AR\nc,,GA\nd,5,AR\ne,4,FL\n";
+        let report = stats(content, &["--top", "2"]);
+
+        let kit = &report[1];
+        assert_eq!(kit.ty, ColumnType::Int);
+        assert_eq!((kit.count, kit.nulls, kit.distinct), (5, 1, 4));
+        assert_eq!(
+            (kit.min.clone(), kit.max.clone()),
+            (Some(json!(2)), Some(json!(17)))
+        );
+        assert_eq!(kit.mean, Some(7.0));
+        assert_eq!(kit.median, Some(4.5));
+        assert!((kit.stddev.unwrap() - 6.7823).abs() < 1e-4);
+
+        let nationality = &report[2];
+        assert_eq!(nationality.mean, None);
+        assert_eq!(nationality.min, Some(json!("AR")));
+        let top = nationality
+            .top
+            .iter()
+            .map(|t| (t.value.as_str(), t.count))
+            .collect::<Vec<_>>();
+        assert_eq!(top, [("AR", 3), ("FL", 1)]);
+    }
+
+    #[test]
+    fn test_approximate_counts() {
+        let mut counts = ValueCounts::new(1000);
+        for i in 0..100_000 {
+            counts.add(i.to_string());
+            // a value that is a third of the stream stays in the summary
+            if i % 2 == 0 {
+                counts.add("AR".into());
+            }
+        }
+        assert!(counts.overflowed);
+        let distinct = counts.distinct() as f64;
+        assert!(
+            (distinct - 100_001.0).abs() / 100_001.0 < 0.03,
+            "{}",
+            distinct
+        );
+        assert_eq!(counts.top(1)[0].value, "AR");
+    }
+
+    #[test]
+    fn test_approximate_median() {
+        let mut profile = Profile::new(10);
+        // 0..200_000 in a scrambled order, the median is 99_999.5
+        for i in 0..200_000u64 {
+            profile.add(json!((i * 7919) % 200_000));
+        }
+        assert_eq!(profile.numbers.len(), MEDIAN_SAMPLE);
+        let column = profile.finish("Kit".into(), ColumnType::Int, 200_000, 1);
+        assert!(column.median_approximate);
+        let median = column.median.unwrap();
+        assert!((median - 99_999.5).abs() < 2_000.0, "{}", median);
+        assert!((column.mean.unwrap() - 99_999.5).abs() < 1e-6);
+
+        // the top counts are lower bounds once the summary overflowed
+        assert!(column.approximate);
+        let row = column.table_row();
+        assert!(row[8].as_str().unwrap().starts_with('~'));
+        assert!(row[10].as_str().unwrap().contains("(~"));
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::{borrow::Cow, io::Write};
+use unicode_width::UnicodeWidthStr;
+
+use super::csv_types::value_text;
+
+/// columns are never narrowed below this many terminal columns to fit the table width
+const MIN_COLUMN_WIDTH: usize = 3;
+
+/// Table collects rows and draws them with Unicode box characters, columns that only hold
+/// numbers are right aligned. Widths are measured in terminal columns, so CJK text lines up.
+#[derive(Debug, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    numeric: Vec<bool>,
+    // cells wider than this are truncated with `…`
+    max_cell_width: Option<usize>,
+    // the widest columns are narrowed until the whole table fits in this width
+    max_width: Option<usize>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<String>) -> Self {
+        let numeric = vec![true; headers.len()];
+        Self {
+            headers,
+            rows: Vec::new(),
+            numeric,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_cell_width(mut self, width: usize) -> Self {
+        self.max_cell_width = Some(width.max(MIN_COLUMN_WIDTH));
+        self
+    }
+
+    pub fn max_width(mut self, width: usize) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+
+    pub fn push_row(&mut self, row: &[Value]) {
+        for (numeric, value) in self.numeric.iter_mut().zip(row) {
+            *numeric &= value.is_number() || value.is_null();
+        }
+        self.rows.push(row.iter().map(cell_text).collect());
+    }
+
+    pub fn render(&self, writer: &mut dyn Write) -> Result<()> {
+        if self.headers.is_empty() {
+            return Ok(());
+        }
+        let widths = self.widths();
+
+        let rule = |left: &str, mid: &str, right: &str| {
+            let parts = widths.iter().map(|w| "─".repeat(w + 2)).collect::<Vec<_>>();
+            format!("{}{}{}\n", left, parts.join(mid), right)
+        };
+        let line = |cells: &[String], align: &dyn Fn(usize) -> bool| {
+            let parts = cells
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (cell, width))| {
+                    let cell = truncate(cell, *width);
+                    let pad = " ".repeat(width.saturating_sub(cell.width()));
+                    if align(i) {
+                        format!(" {}{} ", pad, cell)
+                    } else {
+                        format!(" {}{} ", cell, pad)
+                    }
+                })
+                .collect::<Vec<_>>();
+            format!("│{}│\n", parts.join("│"))
+        };
+
+        writer.write_all(rule("┌", "┬", "┐").as_bytes())?;
+        writer.write_all(line(&self.headers, &|_| false).as_bytes())?;
+        writer.write_all(rule("├", "┼", "┤").as_bytes())?;
+        for row in &self.rows {
+            writer.write_all(line(row, &|i| self.numeric[i]).as_bytes())?;
+        }
+        writer.write_all(rule("└", "┴", "┘").as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// the width of every column, after the cell and table width limits
+    fn widths(&self) -> Vec<usize> {
+        let mut widths = self.headers.iter().map(|h| h.width()).collect::<Vec<_>>();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+        if let Some(max) = self.max_cell_width {
+            widths.iter_mut().for_each(|w| *w = (*w).min(max));
+        }
+        if let Some(max) = self.max_width {
+            // every column takes two spaces of padding and one border
+            let borders = widths.len() * 3 + 1;
+            let mut total = widths.iter().sum::<usize>() + borders;
+            while total > max {
+                let Some(widest) = widths
+                    .iter_mut()
+                    .filter(|w| **w > MIN_COLUMN_WIDTH)
+                    .max_by_key(|w| **w)
+                else {
+                    break;
+                };
+                *widest -= 1;
+                total -= 1;
+            }
+        }
+        widths
+    }
+}
+
+/// cut the text to the width, ending it with `…` if anything was cut. The kept text is measured
+/// as a whole like the cells are, a character can widen the one before it, e.g. the variation
+/// selector of `❤️`.
+fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if text.width() <= width {
+        return Cow::Borrowed(text);
+    }
+    let mut ret = String::new();
+    for c in text.chars() {
+        ret.push(c);
+        if ret.width() + 1 > width {
+            ret.pop();
+            break;
+        }
+    }
+    ret.push('…');
+    Cow::Owned(ret)
+}
+
+/// the text of a cell on a single line
+fn cell_text(value: &Value) -> String {
+    value_text(value).replace(['\n', '\r', '\t'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_table() {
+        let mut table = Table::new(vec!["Name".into(), "Kit".into()]);
+        table.push_row(&[json!("Dennis Harris"), json!(17)]);
+        table.push_row(&[json!("李雷"), json!(null)]);
+        let mut buf = Vec::new();
+        table.render(&mut buf).unwrap();
+        let expected = "\
+┌───────────────┬─────┐
+│ Name          │ Kit │
+├───────────────┼─────┤
+│ Dennis Harris │  17 │
+│ 李雷          │     │
+└───────────────┴─────┘
+";
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_truncate_to_width() {
+        assert_eq!(truncate("Dennis Harris", 8), "Dennis …");
+        assert_eq!(truncate("李雷韩梅梅", 6), "李雷…");
+        assert_eq!(truncate("AR", 8), "AR");
+
+        let mut table = Table::new(vec!["Name".into(), "Position".into()])
+            .max_cell_width(10)
+            .max_width(20);
+        table.push_row(&[json!("Dennis Harris"), json!("Detujgi")]);
+        assert_eq!(table.widths(), [7, 6]);
+        let mut buf = Vec::new();
+        table.render(&mut buf).unwrap();
+        let content = String::from_utf8(buf).unwrap();
+        assert!(content.lines().all(|line| line.width() == 20));
+        assert!(content.contains("│ Dennis… │ Detuj… │"));
+
+        // `❤️` is two columns wide but its characters add up to one
+        let hearts = "❤️".repeat(6);
+        assert!(truncate(&hearts, 3).width() <= 3);
+        let mut table = Table::new(vec!["Name".into(), "Kit".into()]).max_width(5);
+        table.push_row(&[json!(hearts), json!(1)]);
+        let mut buf = Vec::new();
+        table.render(&mut buf).unwrap();
+        let content = String::from_utf8(buf).unwrap();
+        assert!(content.contains("│ ❤️… │   1 │"));
+        assert!(content.lines().all(|line| line.width() == 13));
+    }
+}
@@ -0,0 +1,573 @@
+use anyhow::{bail, Result};
+use chrono::{
+    format::{Fixed, Item, Numeric, StrftimeItems},
+    DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use csv::StringRecord;
+use serde_json::{Number, Value};
+use std::{cmp::Ordering, fmt};
+
+use crate::{ColumnType, CsvTypeOpts};
+
+/// date formats accepted for `date` columns, the output is always ISO 8601 (`YYYY-MM-DD`)
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%m/%d/%Y", "%Y/%m/%d", "%d.%m.%Y"];
+
+/// formats accepted for `timestamp` columns without an offset, RFC 3339 is tried first
+const TIMESTAMP_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// TypeConflict records the cells of a column that did not match the column type,
+/// they are kept as strings and listed once the conversion is done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeConflict {
+    pub column: String,
+    pub expected: ColumnType,
+    /// the `--date` format of the column
+    pub format: Option<String>,
+    pub count: usize,
+    pub first_line: u64,
+    pub first_value: String,
+}
+
+/// TypeConverter turns the cells of a record into typed JSON values according to the column types.
+pub struct TypeConverter {
+    types: Vec<ColumnType>,
+    // when inference is off every cell is kept as it is, including empty ones
+    infer: bool,
+    // the `--date` format of every column, None for the default formats
+    formats: Vec<Option<String>>,
+    timezone: Option<Tz>,
+    conflicts: Vec<Option<TypeConflict>>,
+}
+
+/// infer the column types from a sample of records, `--types` overrides win over the inferred types.
+/// Dates are never inferred since `01/02/2000` is ambiguous, they have to be declared.
+pub fn infer_types(
+    headers: &[String],
+    sample: &[StringRecord],
+    opts: &CsvTypeOpts,
+) -> Result<Vec<ColumnType>> {
+    let mut types = vec![ColumnType::Null; headers.len()];
+    if opts.infer {
+        for record in sample {
+            for (ty, cell) in types.iter_mut().zip(record.iter()) {
+                *ty = merge_types(*ty, detect_type(cell));
+            }
+        }
+    }
+    // a column without any value is still a string column, its cells just all become null
+    for ty in types.iter_mut() {
+        if *ty == ColumnType::Null {
+            *ty = ColumnType::String;
+        }
+    }
+
+    for (column, ty) in &opts.types {
+        match headers.iter().position(|h| h == column) {
+            Some(idx) => types[idx] = *ty,
+            None => bail!("--types refers to unknown column {:?}", column),
+        }
+    }
+    for (column, format) in &opts.dates {
+        match headers.iter().position(|h| h == column) {
+            Some(idx) if has_time(format) => types[idx] = ColumnType::Timestamp,
+            Some(idx) => types[idx] = ColumnType::Date,
+            None => bail!("--date refers to unknown column {:?}", column),
+        }
+    }
+    Ok(types)
+}
+
+/// the `--types` and `--date` options of the columns in `headers`, for the commands that read
+/// several files, a file may not have all of the columns
+pub fn file_types(headers: &[String], opts: &CsvTypeOpts) -> CsvTypeOpts {
+    CsvTypeOpts {
+        types: opts
+            .types
+            .iter()
+            .filter(|(column, _)| headers.contains(column))
+            .cloned()
+            .collect(),
+        dates: opts
+            .dates
+            .iter()
+            .filter(|(column, _)| headers.contains(column))
+            .cloned()
+            .collect(),
+        ..opts.clone()
+    }
+}
+
+/// detect the narrowest type a single cell fits in,
+/// numbers with leading zeros (zip codes, ids) are detected as strings so the zeros are not lost
+pub fn detect_type(cell: &str) -> ColumnType {
+    if cell.is_empty() {
+        ColumnType::Null
+    } else if has_leading_zero(cell) {
+        ColumnType::String
+    } else if parse_bool(cell).is_some() {
+        ColumnType::Bool
+    } else if parse_int(cell).is_some() {
+        ColumnType::Int
+    } else if is_integer_text(cell) {
+        // an id too big for i64 would lose digits as a float
+        ColumnType::String
+    } else if parse_float(cell).is_some() {
+        ColumnType::Float
+    } else {
+        ColumnType::String
+    }
+}
+
+fn merge_types(a: ColumnType, b: ColumnType) -> ColumnType {
+    use ColumnType::*;
+    match (a, b) {
+        (Null, t) | (t, Null) => t,
+        (a, b) if a == b => a,
+        (Int, Float) | (Float, Int) => Float,
+        _ => String,
+    }
+}
+
+/// whether a strftime format reads a time of day, the column is then a timestamp
+pub fn has_time(format: &str) -> bool {
+    StrftimeItems::new(format).any(|item| {
+        matches!(
+            item,
+            Item::Numeric(
+                Numeric::Hour | Numeric::Hour12 | Numeric::Minute | Numeric::Timestamp,
+                _
+            ) | Item::Fixed(Fixed::RFC2822 | Fixed::RFC3339)
+        )
+    })
+}
+
+/// parse a cell to the given type, returns None if the cell does not fit in the type
+pub fn parse_cell(cell: &str, ty: ColumnType) -> Option<Value> {
+    parse_formatted(cell, ty, None, None)
+}
+
+/// parse a cell like [`parse_cell`], dates and timestamps are read with `format` if there is one
+/// and timestamps are written in `timezone`
+pub fn parse_formatted(
+    cell: &str,
+    ty: ColumnType,
+    format: Option<&str>,
+    timezone: Option<Tz>,
+) -> Option<Value> {
+    if cell.is_empty() {
+        return Some(Value::Null);
+    }
+    match ty {
+        ColumnType::Null => None,
+        ColumnType::Bool => parse_bool(cell).map(Value::Bool),
+        ColumnType::Int => parse_int(cell).map(Value::from),
+        ColumnType::Float => parse_float(cell).map(Value::from),
+        ColumnType::Date => match format {
+            Some(format) => NaiveDate::parse_from_str(cell, format).ok(),
+            None => parse_date(cell),
+        }
+        .map(|d| Value::String(d.to_string())),
+        ColumnType::Timestamp => parse_timestamp(cell, format, timezone).map(Value::String),
+        ColumnType::String => Some(Value::String(cell.to_string())),
+    }
+}
+
+fn parse_bool(cell: &str) -> Option<bool> {
+    if cell.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if cell.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn has_leading_zero(cell: &str) -> bool {
+    let digits = cell.strip_prefix(['-', '+']).unwrap_or(cell);
+    digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.')
+}
+
+/// digits with an optional sign, like an integer of any size
+fn is_integer_text(cell: &str) -> bool {
+    let digits = cell.strip_prefix(['-', '+']).unwrap_or(cell);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn parse_int(cell: &str) -> Option<i64> {
+    cell.parse().ok()
+}
+
+pub fn parse_float(cell: &str) -> Option<f64> {
+    // f64::from_str accepts "inf" and "NaN", which are not numbers in a csv file
+    if !cell.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    cell.parse().ok().filter(|f: &f64| f.is_finite())
+}
+
+pub fn parse_date(cell: &str) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|fmt| NaiveDate::parse_from_str(cell, fmt).ok())
+}
+
+/// parse a timestamp and write it as ISO 8601. A timestamp with an offset is moved to `timezone`,
+/// or UTC, so timestamps compare correctly as text. One without an offset is a local time of
+/// `timezone`, it is kept as it is without one.
+fn parse_timestamp(cell: &str, format: Option<&str>, timezone: Option<Tz>) -> Option<String> {
+    let aware = match format {
+        Some(format) => DateTime::parse_from_str(cell, format).ok(),
+        None => DateTime::parse_from_rfc3339(cell).ok(),
+    };
+    if let Some(ts) = aware {
+        return Some(match timezone {
+            Some(tz) => iso_timestamp(ts.with_timezone(&tz)),
+            None => iso_timestamp(ts.with_timezone(&Utc)),
+        });
+    }
+    let naive = match format {
+        Some(format) => NaiveDateTime::parse_from_str(cell, format).ok(),
+        None => TIMESTAMP_FORMATS
+            .iter()
+            .find_map(|fmt| NaiveDateTime::parse_from_str(cell, fmt).ok()),
+    }?;
+    match timezone {
+        // a local time skipped by a daylight saving change does not exist, the earlier of a
+        // repeated one is used
+        Some(tz) => tz.from_local_datetime(&naive).earliest().map(iso_timestamp),
+        None => Some(naive.format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+    }
+}
+
+fn iso_timestamp<T: TimeZone>(ts: DateTime<T>) -> String
+where
+    T::Offset: fmt::Display,
+{
+    ts.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// compare two typed values, numbers compare numerically, also against strings that look like
+/// numbers, so `"10"` and `9` compare the way type inference would see them. Dates are ISO strings
+/// and compare correctly as text. Returns None if either side is null.
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Number(x), Value::Number(y)) => compare_numbers(x, y),
+        (Value::Number(x), Value::String(s)) => match parse_float(s) {
+            Some(y) => x.as_f64()?.partial_cmp(&y),
+            None => Some(x.to_string().as_str().cmp(s)),
+        },
+        (Value::String(_), Value::Number(_)) => compare_values(b, a).map(Ordering::reverse),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        _ => Some(value_text(a).cmp(&value_text(b))),
+    }
+}
+
+/// a total order for sorting, grouping and joining rows, it agrees with [`value_key`]. Unlike
+/// [`compare_values`] a string never equals a number, so the `"02134"` of a string column stays
+/// apart from `2134`. Nulls sort first, then booleans, numbers and strings.
+pub fn sort_order(a: &Value, b: &Value) -> Ordering {
+    let rank = |value: &Value| match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) | Value::Object(_) => 4,
+    };
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => compare_numbers(x, y).unwrap_or(Ordering::Equal),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| value_text(a).cmp(&value_text(b))),
+    }
+}
+
+/// a text key that is equal for values that [`sort_order`] finds equal, so `17` and `17.0` group
+/// and join together, but two 64-bit ids that only differ in the last digit do not
+pub fn value_key(value: &Value) -> String {
+    match value {
+        Value::Number(n) => match exact_int(n) {
+            Some(i) => format!("n:{}", i),
+            None => format!("n:{}", n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => format!("s:{}", s),
+        other => format!("v:{}", other),
+    }
+}
+
+/// whether the key columns of two files are compared by the text of their cells. Every file
+/// infers its types on its own, so the same key can be a number in one file and a string in the
+/// other (`1`, `2` against `1`, `2`, `X3`), typed keys would never match. Ints and floats still
+/// compare as numbers.
+pub fn keys_as_text(left: ColumnType, right: ColumnType) -> bool {
+    left != right && merge_types(left, right) == ColumnType::String
+}
+
+/// the type of a column holding these values, like [`infer_types`] would see them
+pub fn values_type<'a>(values: impl IntoIterator<Item = &'a Value>) -> ColumnType {
+    values.into_iter().fold(ColumnType::Null, |ty, value| {
+        let value_type = match value {
+            Value::Null => ColumnType::Null,
+            Value::Bool(_) => ColumnType::Bool,
+            Value::Number(n) if n.is_f64() => ColumnType::Float,
+            Value::Number(_) => ColumnType::Int,
+            _ => ColumnType::String,
+        };
+        merge_types(ty, value_type)
+    })
+}
+
+/// the key of a value compared by its text, see [`keys_as_text`], `1` and `"1"` have the same one
+pub fn text_key(text: &str) -> String {
+    format!("s:{}", text)
+}
+
+/// the exact value of an integer, or of a float without a fraction
+fn exact_int(n: &Number) -> Option<i128> {
+    n.as_i64()
+        .map(i128::from)
+        .or_else(|| n.as_u64().map(i128::from))
+        .or_else(|| {
+            n.as_f64()
+                .filter(|f| f.fract() == 0.0 && f.abs() < i128::MAX as f64)
+                .map(|f| f as i128)
+        })
+}
+
+/// integers compare exactly, f64 is only used when one side has a fraction, and then it is
+/// below 2^53 where every integer is exact in f64
+fn compare_numbers(x: &Number, y: &Number) -> Option<Ordering> {
+    match (exact_int(x), exact_int(y)) {
+        (Some(x), Some(y)) => Some(x.cmp(&y)),
+        _ => x.as_f64()?.partial_cmp(&y.as_f64()?),
+    }
+}
+
+/// the text of a value as it would be written to a csv cell
+pub fn value_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl TypeConverter {
+    pub fn new(types: Vec<ColumnType>, infer: bool) -> Self {
+        let conflicts = vec![None; types.len()];
+        Self {
+            formats: vec![None; types.len()],
+            types,
+            infer,
+            timezone: None,
+            conflicts,
+        }
+    }
+
+    /// a converter that also reads the `--date` columns with their format and writes the
+    /// timestamps in `--timezone`, the types come from [`infer_types`]
+    pub fn from_opts(headers: &[String], types: Vec<ColumnType>, opts: &CsvTypeOpts) -> Self {
+        let mut converter = Self::new(types, opts.infer);
+        for (column, format) in &opts.dates {
+            if let Some(idx) = headers.iter().position(|h| h == column) {
+                converter.formats[idx] = Some(format.clone());
+            }
+        }
+        converter.timezone = opts.timezone;
+        converter
+    }
+
+    /// convert a record to a list of values aligned with the headers
+    pub fn convert(&mut self, headers: &[String], record: &StringRecord) -> Vec<Value> {
+        (0..self.types.len())
+            .map(|idx| self.convert_cell(headers, record, idx))
+            .collect()
+    }
+
+    /// convert the cell at `idx` of the record, a cell that does not match its column type
+    /// is kept as a string and recorded as a conflict
+    pub fn convert_cell(&mut self, headers: &[String], record: &StringRecord, idx: usize) -> Value {
+        let ty = self.types[idx];
+        match record.get(idx) {
+            None => Value::Null,
+            Some(cell) if !self.infer && ty == ColumnType::String => cell.into(),
+            Some(cell) => {
+                match parse_formatted(cell, ty, self.formats[idx].as_deref(), self.timezone) {
+                    Some(value) => value,
+                    None => {
+                        let conflict = self.conflicts[idx].get_or_insert_with(|| TypeConflict {
+                            column: headers[idx].clone(),
+                            expected: ty,
+                            format: self.formats[idx].clone(),
+                            count: 0,
+                            first_line: record.position().map(|p| p.line()).unwrap_or_default(),
+                            first_value: cell.to_string(),
+                        });
+                        conflict.count += 1;
+                        cell.into()
+                    }
+                }
+            }
+        }
+    }
+
+    /// the type conflicts found so far, one entry per column
+    pub fn conflicts(&self) -> Vec<TypeConflict> {
+        self.conflicts.iter().flatten().cloned().collect()
+    }
+}
+
+impl fmt::Display for TypeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {:?} ({}", self.column, self.expected)?;
+        if let Some(format) = &self.format {
+            write!(f, " {:?}", format)?;
+        }
+        write!(
+            f,
+            "): {} value(s) kept as strings, first at line {}: {:?}",
+            self.count, self.first_line, self.first_value
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records(rows: &[&[&str]]) -> Vec<StringRecord> {
+        rows.iter()
+            .map(|r| StringRecord::from(r.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_types() {
+        let headers = ["i", "f", "b", "s", "z", "e", "id"].map(|h| h.to_string());
+        let sample = records(&[
+            &["17", "1", "true", "AR", "007", "", "12345678901234567890"],
+            &["", "2.5", "FALSE", "1", "010", "", "3"],
+        ]);
+        let types = infer_types(&headers, &sample, &CsvTypeOpts::default()).unwrap();
+        use ColumnType::*;
+        assert_eq!(types, [Int, Float, Bool, String, String, String, String]);
+        assert_eq!(detect_type("-9223372036854775809"), String);
+        assert_eq!(detect_type("9223372036854775807"), Int);
+        assert_eq!(detect_type("1e30"), Float);
+    }
+
+    #[test]
+    fn test_type_overrides() {
+        let headers = ["Kit Number", "DOB"].map(String::from);
+        let opts = CsvTypeOpts {
+            types: vec![("DOB".into(), ColumnType::Date)],
+            ..Default::default()
+        };
+        let sample = records(&[&["17", "06/26/2055"]]);
+        let types = infer_types(&headers, &sample, &opts).unwrap();
+        assert_eq!(types, [ColumnType::Int, ColumnType::Date]);
+        assert_eq!(parse_cell("01", ColumnType::Int), Some(Value::from(1)));
+
+        let opts = CsvTypeOpts {
+            types: vec![("Kit".into(), ColumnType::Int)],
+            ..Default::default()
+        };
+        assert!(infer_types(&headers, &sample, &opts).is_err());
+    }
+
+    #[test]
+    fn test_convert_records_conflicts() {
+        let headers = ["Kit Number", "DOB"].map(String::from);
+        let mut converter = TypeConverter::new(vec![ColumnType::Int, ColumnType::Date], true);
+        let values = converter.convert(&headers, &StringRecord::from(vec!["17", "06/26/2055"]));
+        assert_eq!(values, [Value::from(17), Value::from("2055-06-26")]);
+
+        converter.convert(&headers, &StringRecord::from(vec!["N/A", ""]));
+        converter.convert(&headers, &StringRecord::from(vec!["-", ""]));
+        let conflicts = converter.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].column, "Kit Number");
+        assert_eq!(conflicts[0].count, 2);
+        assert_eq!(conflicts[0].first_value, "N/A");
+    }
+
+    #[test]
+    fn test_declared_date_formats() {
+        let headers = ["DOB", "Seen", "Signed"].map(String::from);
+        let opts = CsvTypeOpts {
+            dates: vec![
+                ("DOB".into(), "%d %b %Y".into()),
+                ("Seen".into(), "%m/%d/%Y %H:%M".into()),
+                ("Signed".into(), "%Y-%m-%d %H:%M:%S %z".into()),
+            ],
+            ..Default::default()
+        };
+        let types = infer_types(&headers, &[], &opts).unwrap();
+        use ColumnType::{Date, Timestamp};
+        assert_eq!(types, [Date, Timestamp, Timestamp]);
+
+        let record = StringRecord::from(vec![
+            "26 Jun 2055",
+            "03/30/2025 02:30",
+            "2025-01-02 10:00:00 +0100",
+        ]);
+        let mut converter = TypeConverter::from_opts(&headers, types.clone(), &opts);
+        let values = converter.convert(&headers, &record);
+        assert_eq!(
+            values,
+            ["2055-06-26", "2025-03-30T02:30:00", "2025-01-02T09:00:00Z"].map(Value::from)
+        );
+
+        // 02:30 does not exist in Paris that night, the clocks went from 02:00 to 03:00
+        let opts = CsvTypeOpts {
+            timezone: Some(Tz::Europe__Paris),
+            ..opts
+        };
+        let mut converter = TypeConverter::from_opts(&headers, types, &opts);
+        let values = converter.convert(&headers, &record);
+        assert_eq!(values[2], Value::from("2025-01-02T10:00:00+01:00"));
+        converter.convert(
+            &headers,
+            &StringRecord::from(vec!["1955-06-26", "03/31/2025 02:30", ""]),
+        );
+        let conflicts = converter.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[1].to_string(),
+            r#"column "Seen" (timestamp "%m/%d/%Y %H:%M"): 1 value(s) kept as strings, first at line 0: "03/30/2025 02:30""#
+        );
+        assert_eq!(
+            parse_cell("2025-03-31 02:30:00.5", Timestamp),
+            Some(Value::from("2025-03-31T02:30:00.500"))
+        );
+    }
+
+    #[test]
+    fn test_value_keys_are_exact() {
+        let pairs = [
+            (
+                json!(1234567890123456789_i64),
+                json!(1234567890123456788_i64),
+            ),
+            (json!("02134"), json!("2134")),
+            (json!(2134), json!("2134")),
+            (json!(u64::MAX), json!(u64::MAX - 1)),
+        ];
+        for (a, b) in &pairs {
+            assert_ne!(value_key(a), value_key(b));
+            assert_ne!(sort_order(a, b), Ordering::Equal);
+        }
+        let (a, b) = (json!(17), json!(17.0));
+        assert_eq!(value_key(&a), value_key(&b));
+        assert_eq!(sort_order(&a, &b), Ordering::Equal);
+        assert_eq!(
+            sort_order(&json!(9007199254740993_i64), &json!(9007199254740992.0)),
+            Ordering::Greater
+        );
+    }
+}
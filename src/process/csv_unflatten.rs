@@ -0,0 +1,275 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+/// the largest array index in a column name, every row gets arrays this long so a typo like
+/// `tags[99999999999]` is an error
+const MAX_INDEX: usize = 10_000;
+
+/// Unflatten rebuilds nested objects and arrays from flattened column names, the reverse of what
+/// `rcli csv --from` does: `address.city` becomes `{"address": {"city": ...}}` and `tags[0]`
+/// becomes `{"tags": [...]}`. The paths are resolved once from the headers, so conflicting
+/// columns are reported before any row is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unflatten {
+    root: Vec<(String, Node)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// the position of the column in the row, None until the path of the column is complete
+    Leaf(Option<usize>),
+    Object(Vec<(String, Node)>),
+    /// missing indices (`tags[0]` and `tags[2]` without `tags[1]`) are written as null
+    Array(Vec<Option<Node>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+impl Unflatten {
+    pub fn new(names: &[String], separator: &str) -> Result<Self> {
+        if separator.is_empty() {
+            bail!("--unflatten needs a non-empty --separator");
+        }
+        let mut root = Node::Object(Vec::new());
+        for (idx, name) in names.iter().enumerate() {
+            let path = parse_path(name, separator);
+            if let Some(i) = path.iter().find_map(|step| match step {
+                Step::Index(i) if *i > MAX_INDEX => Some(i),
+                _ => None,
+            }) {
+                bail!(
+                    "column {:?}: array index {} is above the limit of {}",
+                    name,
+                    i,
+                    MAX_INDEX
+                );
+            }
+            if let Err((other, depth, reason)) = insert(&mut root, &path, idx) {
+                bail!(
+                    "columns {:?} and {:?} conflict: {:?} cannot be {}",
+                    names[other],
+                    name,
+                    path_text(&path[..depth], separator),
+                    reason
+                );
+            }
+        }
+        let Node::Object(root) = root else {
+            unreachable!("the root is always an object");
+        };
+        Ok(Self { root })
+    }
+
+    /// the top-level keys of the nested rows, in output order
+    pub fn names(&self) -> Vec<String> {
+        self.root.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// nest the values of a flat row, the values are in the same order as the names
+    pub fn row(&self, row: Map<String, Value>) -> Map<String, Value> {
+        let mut values = row.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+        self.root
+            .iter()
+            .map(|(key, node)| (key.clone(), node.build(&mut values)))
+            .collect()
+    }
+}
+
+/// split `a.b[0][1]` into `a`, `b`, `0`, `1`, a part that does not end in valid `[n]` suffixes
+/// is a plain key, so `Kit Number` and `x[y]` are kept as they are
+fn parse_path(name: &str, separator: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for part in name.split(separator) {
+        let mut key = part;
+        let mut indices = Vec::new();
+        while let Some((head, index)) = key.strip_suffix(']').and_then(|k| k.rsplit_once('[')) {
+            let Ok(index) = index.parse::<usize>() else {
+                break;
+            };
+            indices.push(index);
+            key = head;
+        }
+        // `[0]` at the start has nothing to index into
+        if key.is_empty() && steps.is_empty() {
+            key = part;
+            indices.clear();
+        }
+        if !key.is_empty() {
+            steps.push(Step::Key(key.to_string()));
+        }
+        steps.extend(indices.into_iter().rev().map(Step::Index));
+    }
+    steps
+}
+
+fn path_text(path: &[Step], separator: &str) -> String {
+    let mut ret = String::new();
+    for step in path {
+        match step {
+            Step::Key(key) if ret.is_empty() => ret.push_str(key),
+            Step::Key(key) => {
+                ret.push_str(separator);
+                ret.push_str(key);
+            }
+            Step::Index(i) => ret.push_str(&format!("[{}]", i)),
+        }
+    }
+    ret
+}
+
+/// add the column at `idx` to the tree, on a conflict returns the column that is already there,
+/// the depth of the path where they collide and why
+fn insert(root: &mut Node, path: &[Step], idx: usize) -> Result<(), (usize, usize, &'static str)> {
+    let mut node = root;
+    for (depth, step) in path.iter().enumerate() {
+        let rest = &path[depth + 1..];
+        node = match (node, step) {
+            (Node::Object(entries), Step::Key(key)) => {
+                let pos = match entries.iter().position(|(k, _)| k == key) {
+                    Some(pos) => pos,
+                    None => {
+                        entries.push((key.clone(), empty_node(rest)));
+                        entries.len() - 1
+                    }
+                };
+                &mut entries[pos].1
+            }
+            (Node::Array(items), Step::Index(i)) => {
+                if items.len() <= *i {
+                    items.resize(*i + 1, None);
+                }
+                items[*i].get_or_insert_with(|| empty_node(rest))
+            }
+            (node, step) => {
+                let reason = match (&*node, step) {
+                    (Node::Leaf(_), Step::Key(_)) => "both a value and an object",
+                    (Node::Leaf(_), Step::Index(_)) => "both a value and an array",
+                    (Node::Object(_), _) => "both an object and an array",
+                    (Node::Array(_), _) => "both an array and an object",
+                };
+                return Err((node.first_leaf(), depth, reason));
+            }
+        };
+    }
+    match node {
+        Node::Leaf(None) => {
+            *node = Node::Leaf(Some(idx));
+            Ok(())
+        }
+        Node::Leaf(Some(other)) => Err((*other, path.len(), "set twice")),
+        Node::Object(_) => Err((node.first_leaf(), path.len(), "both an object and a value")),
+        Node::Array(_) => Err((node.first_leaf(), path.len(), "both an array and a value")),
+    }
+}
+
+/// the node a new path step creates, the leaf is filled in once the path is complete
+fn empty_node(rest: &[Step]) -> Node {
+    match rest.first() {
+        None => Node::Leaf(None),
+        Some(Step::Key(_)) => Node::Object(Vec::new()),
+        Some(Step::Index(_)) => Node::Array(Vec::new()),
+    }
+}
+
+impl Node {
+    fn first_leaf(&self) -> usize {
+        match self {
+            Node::Leaf(idx) => idx.unwrap_or_default(),
+            Node::Object(entries) => entries.first().map_or(0, |(_, n)| n.first_leaf()),
+            Node::Array(items) => items.iter().flatten().next().map_or(0, Node::first_leaf),
+        }
+    }
+
+    fn build(&self, values: &mut [Value]) -> Value {
+        match self {
+            Node::Leaf(idx) => idx
+                .and_then(|idx| values.get_mut(idx))
+                .map(Value::take)
+                .unwrap_or_default(),
+            Node::Object(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, node)| (key.clone(), node.build(values)))
+                    .collect(),
+            ),
+            Node::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| item.as_ref().map_or(Value::Null, |n| n.build(values)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn unflatten(names: &[&str], values: Value) -> Result<Value> {
+        let names = names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let unflatten = Unflatten::new(&names, ".")?;
+        let row = names
+            .iter()
+            .cloned()
+            .zip(values.as_array().unwrap().clone());
+        Ok(Value::Object(unflatten.row(row.collect())))
+    }
+
+    #[test]
+    fn test_unflatten_row() {
+        let names = [
+            "Name",
+            "address.city",
+            "address.geo.lat",
+            "tags[0]",
+            "tags[2]",
+            "m[0][1]",
+        ];
+        let row = unflatten(&names, json!(["Dennis", "Rosario", 1.5, "a", "c", 7])).unwrap();
+        assert_eq!(
+            row,
+            json!({
+                "Name": "Dennis",
+                "address": {"city": "Rosario", "geo": {"lat": 1.5}},
+                "tags": ["a", null, "c"],
+                "m": [[null, 7]]
+            })
+        );
+        assert_eq!(
+            parse_path("Kit Number", "."),
+            [Step::Key("Kit Number".into())]
+        );
+        assert_eq!(parse_path("[0]", "."), [Step::Key("[0]".into())]);
+    }
+
+    #[test]
+    fn test_unflatten_conflicts() {
+        let err = unflatten(&["a", "a.b"], json!([1, 2])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"columns "a" and "a.b" conflict: "a" cannot be both a value and an object"#
+        );
+        let err = unflatten(&["a.b[0]", "a"], json!([1, 2])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"columns "a.b[0]" and "a" conflict: "a" cannot be both an object and a value"#
+        );
+        let err = unflatten(&["x.tags[0]", "x.tags.a"], json!([1, 2])).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with(r#""x.tags" cannot be both an array and an object"#));
+
+        let err = unflatten(&["tags[99999999999]"], json!([1])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"column "tags[99999999999]": array index 99999999999 is above the limit of 10000"#
+        );
+        assert!(unflatten(&["tags[10000]"], json!([1])).is_ok());
+    }
+}
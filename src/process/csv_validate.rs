@@ -0,0 +1,436 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{fmt, fs};
+
+use super::csv_reader::CsvSource;
+use super::csv_types::{infer_types, parse_cell, parse_formatted, TypeConverter};
+use super::csv_writer::create_row_writer;
+use crate::{get_writer, ColumnType, CsvValidateOpts};
+
+/// Schema is the YAML document given to `rcli csv validate --schema`, e.g.
+///
+/// ```yaml
+/// columns:
+///   - name: Name
+///     required: true
+///     pattern: "^[A-Z]"
+///   - name: Kit Number
+///     type: int
+///     min: 1
+///     max: 99
+///   - name: Nationality
+///     enum: [AR, GA, FL]
+/// allow_extra: false
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub columns: Vec<ColumnSchema>,
+    /// whether the input may have columns that are not declared in the schema
+    #[serde(default = "default_allow_extra")]
+    pub allow_extra: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    pub name: String,
+    #[serde(rename = "type", default = "default_type")]
+    pub ty: ColumnType,
+    /// a required column has to be present and none of its cells may be empty
+    #[serde(default)]
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<Value>>,
+    /// bounds for int, float and date columns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<Value>,
+    /// length bounds for string cells
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+}
+
+/// Violation is a single cell (or header) that does not satisfy the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub line: u64,
+    pub column: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// ValidationReport is returned by [`process_csv_validate`], the violations themselves are given
+/// to the caller as they are found.
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub valid_rows: usize,
+    pub violations: usize,
+}
+
+/// the schema checks of a column, with the regex compiled and the bounds resolved
+struct ColumnRule<'a> {
+    schema: &'a ColumnSchema,
+    // index of the column in the input, None if the column is missing
+    idx: Option<usize>,
+    // the `--date` format of the column, None for the default formats
+    format: Option<&'a str>,
+    pattern: Option<Regex>,
+    values: Option<Vec<String>>,
+    min: Option<Bound>,
+    max: Option<Bound>,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+enum Bound {
+    Number(f64),
+    Date(String),
+}
+
+fn default_allow_extra() -> bool {
+    true
+}
+
+fn default_type() -> ColumnType {
+    ColumnType::String
+}
+
+/// process_csv_validate checks every row against the schema and gives every violation to
+/// `on_violation` as it is found, so a large file with many violations is not kept in memory.
+/// With `--output` the rows without violations are written like `rcli csv` would convert them.
+pub fn process_csv_validate(
+    opts: &CsvValidateOpts,
+    mut on_violation: impl FnMut(&Violation),
+) -> Result<ValidationReport> {
+    let schema = Schema::load(&opts.schema)?;
+    let mut reader = CsvSource::open(&opts.input, &opts.reader)?;
+    let headers = reader.headers().to_vec();
+
+    let mut report = ValidationReport::default();
+    let mut header_violations = Vec::new();
+    let rules = schema.rules(&headers, &opts.types.dates, &mut header_violations)?;
+    for violation in &header_violations {
+        on_violation(violation);
+    }
+    report.violations = header_violations.len();
+
+    let sample = reader
+        .by_ref()
+        .take(opts.types.sample)
+        .collect::<Result<Vec<_>>>()?;
+    // the declared column types win over the inferred ones
+    let mut types = infer_types(&headers, &sample, &opts.types)?;
+    for rule in &rules {
+        if let Some(idx) = rule.idx {
+            types[idx] = rule.schema.ty;
+        }
+    }
+    let mut converter = TypeConverter::from_opts(&headers, types, &opts.types);
+
+    let mut writer = match &opts.output {
+        Some(output) => Some(create_row_writer(
+            opts.format,
+            get_writer(output)?,
+            &headers,
+        )),
+        None => None,
+    };
+    for result in sample.into_iter().map(Ok).chain(reader) {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        report.rows += 1;
+
+        let before = report.violations;
+        for rule in &rules {
+            if let Some(idx) = rule.idx {
+                let cell = record.get(idx).unwrap_or_default();
+                for reason in rule.check(cell) {
+                    on_violation(&Violation {
+                        line,
+                        column: rule.schema.name.clone(),
+                        value: cell.to_string(),
+                        reason,
+                    });
+                    report.violations += 1;
+                }
+            }
+        }
+        if report.violations > before {
+            continue;
+        }
+
+        report.valid_rows += 1;
+        if let Some(writer) = writer.as_mut() {
+            let values = converter.convert(&headers, &record);
+            let row = headers.iter().cloned().zip(values).collect::<Map<_, _>>();
+            writer.write_row(&row)?;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+    Ok(report)
+}
+
+impl Schema {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).with_context(|| format!("invalid schema {}", path))
+    }
+
+    /// resolve the schema against the headers and the `--date` formats, missing required columns
+    /// and undeclared columns are reported as violations of the header line
+    fn rules<'a>(
+        &'a self,
+        headers: &[String],
+        dates: &'a [(String, String)],
+        violations: &mut Vec<Violation>,
+    ) -> Result<Vec<ColumnRule<'a>>> {
+        let header_violation = |column: &str, reason: &str| Violation {
+            line: 1,
+            column: column.to_string(),
+            value: String::new(),
+            reason: reason.to_string(),
+        };
+
+        let mut rules = Vec::with_capacity(self.columns.len());
+        for column in &self.columns {
+            let idx = headers.iter().position(|h| h == &column.name);
+            if idx.is_none() && column.required {
+                violations.push(header_violation(&column.name, "required column is missing"));
+            }
+            let format = dates
+                .iter()
+                .find(|(name, _)| name == &column.name)
+                .map(|(_, format)| format.as_str());
+            rules.push(ColumnRule::new(column, idx, format)?);
+        }
+        if !self.allow_extra {
+            for header in headers {
+                if !self.columns.iter().any(|c| &c.name == header) {
+                    violations.push(header_violation(header, "column is not in the schema"));
+                }
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl<'a> ColumnRule<'a> {
+    fn new(schema: &'a ColumnSchema, idx: Option<usize>, format: Option<&'a str>) -> Result<Self> {
+        let context = || format!("column {:?}", schema.name);
+        let pattern = schema
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .with_context(context)?;
+        let values = schema.values.as_ref().map(|values| {
+            values
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect()
+        });
+        let min = schema
+            .min
+            .as_ref()
+            .map(|v| Bound::new(v, schema.ty, format))
+            .transpose()
+            .with_context(context)?;
+        let max = schema
+            .max
+            .as_ref()
+            .map(|v| Bound::new(v, schema.ty, format))
+            .transpose()
+            .with_context(context)?;
+        Ok(Self {
+            schema,
+            idx,
+            format,
+            pattern,
+            values,
+            min,
+            max,
+        })
+    }
+
+    /// check a cell, returns every reason it violates the schema
+    fn check(&self, cell: &str) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if cell.is_empty() {
+            if self.schema.required {
+                reasons.push("required value is missing".to_string());
+            }
+            return reasons;
+        }
+        let ty = self.schema.ty;
+        let value = parse_formatted(cell, ty, self.format, None);
+        if value.is_none() {
+            reasons.push(format!("expected a {} value", ty));
+        }
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(cell) {
+                reasons.push(format!("does not match pattern {:?}", pattern.as_str()));
+            }
+        }
+        if let Some(values) = &self.values {
+            if !values.iter().any(|v| v == cell) {
+                reasons.push(format!("is not one of {:?}", values));
+            }
+        }
+        let len = cell.chars().count();
+        if let Some(min) = self.schema.min_length.filter(|min| len < *min) {
+            reasons.push(format!("is shorter than {} characters", min));
+        }
+        if let Some(max) = self.schema.max_length.filter(|max| len > *max) {
+            reasons.push(format!("is longer than {} characters", max));
+        }
+
+        let Some(value) = value else {
+            return reasons;
+        };
+        let bound = match (&value, ty) {
+            (Value::Number(n), _) => n.as_f64().map(Bound::Number),
+            (Value::String(s), ColumnType::Date | ColumnType::Timestamp) => {
+                Some(Bound::Date(s.clone()))
+            }
+            _ => None,
+        };
+        if let Some(bound) = bound {
+            if let Some(min) = self.min.as_ref().filter(|min| &bound < min) {
+                reasons.push(format!("is less than the minimum {}", min));
+            }
+            if let Some(max) = self.max.as_ref().filter(|max| &bound > max) {
+                reasons.push(format!("is greater than the maximum {}", max));
+            }
+        }
+        reasons
+    }
+}
+
+impl Bound {
+    /// a date or timestamp bound is written in the `--date` format of the column or in one of
+    /// the default formats
+    fn new(value: &Value, ty: ColumnType, format: Option<&str>) -> Result<Self> {
+        match (value, ty) {
+            (Value::Number(n), ColumnType::Int | ColumnType::Float) => {
+                Ok(Bound::Number(n.as_f64().unwrap_or_default()))
+            }
+            (Value::String(s), ColumnType::Date | ColumnType::Timestamp) => {
+                match parse_formatted(s, ty, format, None).or_else(|| parse_cell(s, ty)) {
+                    Some(Value::String(date)) => Ok(Bound::Date(date)),
+                    _ => bail!("invalid {} bound {:?}", ty, s),
+                }
+            }
+            _ => bail!(
+                "min/max {} does not apply to a {} column, use min_length/max_length for strings",
+                value,
+                ty
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Number(n) => write!(f, "{}", n),
+            Bound::Date(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {:?}: value {:?} {}",
+            self.line, self.column, self.value, self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(yaml: &str) -> ColumnSchema {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_column_checks() {
+        let schema = column("{name: Kit Number, type: int, required: true, min: 1, max: 99}");
+        let rule = ColumnRule::new(&schema, Some(0), None).unwrap();
+        assert!(rule.check("17").is_empty());
+        assert_eq!(rule.check(""), ["required value is missing"]);
+        assert_eq!(rule.check("abc"), ["expected a int value"]);
+        assert_eq!(rule.check("100"), ["is greater than the maximum 99"]);
+
+        let schema = column("{name: Nationality, enum: [AR, GA], pattern: '^[A-Z]{2}$'}");
+        let rule = ColumnRule::new(&schema, Some(0), None).unwrap();
+        assert!(rule.check("").is_empty());
+        assert!(rule.check("AR").is_empty());
+        let reasons = rule.check("ar");
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons[0].starts_with("does not match pattern"));
+        assert!(reasons[1].starts_with("is not one of"));
+        assert!(rule.check("FL")[0].starts_with("is not one of"));
+
+        let schema = column("{name: DOB, type: date, min: '01/01/1950'}");
+        let rule = ColumnRule::new(&schema, Some(0), None).unwrap();
+        assert!(rule.check("06/26/2055").is_empty());
+        assert_eq!(
+            rule.check("08/26/1920"),
+            ["is less than the minimum 1950-01-01"]
+        );
+
+        // a `--date` format is used for the cells and the bounds
+        let schema = column("{name: DOB, type: date, min: '01 Jan 1950'}");
+        let rule = ColumnRule::new(&schema, Some(0), Some("%d %b %Y")).unwrap();
+        assert!(rule.check("26 Jun 2055").is_empty());
+        assert_eq!(
+            rule.check("26 Aug 1920"),
+            ["is less than the minimum 1950-01-01"]
+        );
+        assert_eq!(rule.check("06/26/2055"), ["expected a date value"]);
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let schema = column("{name: Name, min: 1}");
+        assert!(ColumnRule::new(&schema, Some(0), None).is_err());
+        assert!(serde_yaml::from_str::<ColumnSchema>("{name: Name, typo: 1}").is_err());
+    }
+
+    #[test]
+    fn test_header_violations() {
+        let schema: Schema = serde_yaml::from_str(
+            "{columns: [{name: Name, required: true}, {name: Kit}], allow_extra: false}",
+        )
+        .unwrap();
+        let mut violations = Vec::new();
+        let headers = ["Kit".to_string(), "DOB".to_string()];
+        let rules = schema.rules(&headers, &[], &mut violations).unwrap();
+        assert_eq!(rules[1].idx, Some(0));
+        let reasons = violations
+            .iter()
+            .map(|v| v.reason.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            ["required column is missing", "column is not in the schema"]
+        );
+    }
+}
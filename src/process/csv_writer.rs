@@ -0,0 +1,519 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::io::Write;
+
+use super::csv_types::value_text;
+use crate::OutputFormat;
+
+/// RowWriter writes rows one at a time, so a conversion never holds more than one row in memory.
+/// `finish` writes whatever closes the document (e.g. the `]` of a JSON array) and flushes.
+pub trait RowWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// create the [`RowWriter`] for the output format. `headers` are the columns of every row if they
+/// are known up front, the table formats write them even for an empty result. Without them the
+/// columns are the keys of the first row.
+pub fn create_row_writer(
+    format: OutputFormat,
+    writer: Box<dyn Write>,
+    headers: &[String],
+) -> Box<dyn RowWriter> {
+    match format {
+        OutputFormat::Json => Box::new(JsonWriter { writer, rows: 0 }),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter { writer }),
+        OutputFormat::Yaml => Box::new(YamlWriter { writer, rows: 0 }),
+        OutputFormat::Toml => Box::new(TomlWriter { writer, rows: 0 }),
+        OutputFormat::Markdown => Box::new(MarkdownWriter {
+            writer,
+            headers: headers.to_vec(),
+            started: false,
+        }),
+        OutputFormat::Html => create_html_writer(writer, headers, false),
+    }
+}
+
+/// create a [`RowWriter`] for an HTML table, `page` wraps it in a standalone page where clicking a
+/// column header sorts the rows
+pub fn create_html_writer(
+    writer: Box<dyn Write>,
+    headers: &[String],
+    page: bool,
+) -> Box<dyn RowWriter> {
+    Box::new(HtmlWriter {
+        writer,
+        headers: headers.to_vec(),
+        started: false,
+        page,
+    })
+}
+
+/// create a [`RowWriter`] that writes csv, the header row is written right away so that an empty
+/// result still has its columns. Nested values are written as JSON text.
+pub fn create_csv_writer(
+    writer: Box<dyn Write>,
+    delimiter: u8,
+    headers: &[String],
+) -> Result<Box<dyn RowWriter>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    writer.write_record(headers)?;
+    Ok(Box::new(CsvWriter { writer }))
+}
+
+/// writes one csv record per row, in the order of the row's keys
+struct CsvWriter {
+    writer: csv::Writer<Box<dyn Write>>,
+}
+
+/// writes a pretty printed JSON array, the same layout as `serde_json::to_string_pretty(&rows)`
+struct JsonWriter {
+    writer: Box<dyn Write>,
+    rows: usize,
+}
+
+/// writes one JSON object per line
+struct NdjsonWriter {
+    writer: Box<dyn Write>,
+}
+
+/// writes a YAML sequence, every row is serialized as a one-item sequence and appended
+struct YamlWriter {
+    writer: Box<dyn Write>,
+    rows: usize,
+}
+
+/// writes every row as a `[[rows]]` table, TOML has no top-level arrays
+struct TomlWriter {
+    writer: Box<dyn Write>,
+    rows: usize,
+}
+
+/// writes a GitHub-flavored Markdown table, the columns are the keys of the first row unless
+/// they were given
+struct MarkdownWriter {
+    writer: Box<dyn Write>,
+    headers: Vec<String>,
+    // whether the header row was written
+    started: bool,
+}
+
+/// writes an HTML table, the columns are the keys of the first row unless they were given
+struct HtmlWriter {
+    writer: Box<dyn Write>,
+    headers: Vec<String>,
+    // whether the table and its header row were written
+    started: bool,
+    page: bool,
+}
+
+const HTML_PAGE_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>rcli csv</title>
+<style>
+table { border-collapse: collapse; font-family: sans-serif; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; }
+th { background: #f4f4f4; cursor: pointer; user-select: none; }
+th[data-order="asc"]::after { content: " ▲"; }
+th[data-order="desc"]::after { content: " ▼"; }
+</style>
+</head>
+<body>
+"#;
+
+// numbers sort numerically, everything else as text
+const HTML_PAGE_TAIL: &str = r#"<script>
+document.querySelectorAll("th").forEach((th) => {
+  th.addEventListener("click", () => {
+    const body = th.closest("table").tBodies[0];
+    const asc = th.dataset.order !== "asc";
+    th.parentElement.querySelectorAll("th").forEach((h) => delete h.dataset.order);
+    th.dataset.order = asc ? "asc" : "desc";
+    const cell = (row) => row.cells[th.cellIndex].textContent;
+    const rows = Array.from(body.rows).sort((a, b) => {
+      const [x, y] = [cell(a), cell(b)];
+      const [m, n] = [Number(x), Number(y)];
+      const order = x !== "" && y !== "" && !isNaN(m) && !isNaN(n) ? m - n : x.localeCompare(y);
+      return asc ? order : -order;
+    });
+    body.append(...rows);
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+impl RowWriter for CsvWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        self.writer.write_record(row.values().map(value_text))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowWriter for JsonWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        let sep = if self.rows == 0 { "[\n" } else { ",\n" };
+        self.writer.write_all(sep.as_bytes())?;
+        // indent the pretty printed object by one level so it sits inside the array
+        let content = serde_json::to_string_pretty(row)?;
+        for (i, line) in content.lines().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            write!(self.writer, "  {}", line)?;
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let end = if self.rows == 0 { "[]" } else { "\n]" };
+        self.writer.write_all(end.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowWriter for NdjsonWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, row)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowWriter for YamlWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        serde_yaml::to_writer(&mut self.writer, &[row])?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if self.rows == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowWriter for TomlWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        self.rows += 1;
+        let table = json_to_toml(&Value::Object(row.clone()))
+            .with_context(|| format!("row {}", self.rows))?;
+        let mut doc = toml::Table::new();
+        doc.insert("rows".into(), toml::Value::Array(vec![table]));
+        if self.rows > 1 {
+            self.writer.write_all(b"\n")?;
+        }
+        // keys that are not valid bare keys (like "Kit Number") are quoted by the serializer
+        self.writer.write_all(toml::to_string(&doc)?.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if self.rows == 0 {
+            self.writer.write_all(b"rows = []\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl RowWriter for MarkdownWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        if !self.started {
+            if self.headers.is_empty() {
+                self.headers = row.keys().cloned().collect();
+            }
+            self.start()?;
+        }
+        let cells = self
+            .headers
+            .iter()
+            .map(|h| markdown_escape(&row.get(h).map(value_text).unwrap_or_default()));
+        writeln!(self.writer, "| {} |", cells.collect::<Vec<_>>().join(" | "))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // an empty result with known columns is a table without rows, a table needs columns
+        if !self.started && !self.headers.is_empty() {
+            self.start()?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl MarkdownWriter {
+    /// the header row and the separator row below it
+    fn start(&mut self) -> Result<()> {
+        let headers = self.headers.iter().map(|h| markdown_escape(h));
+        writeln!(
+            self.writer,
+            "| {} |",
+            headers.collect::<Vec<_>>().join(" | ")
+        )?;
+        let rule = vec!["---"; self.headers.len()];
+        writeln!(self.writer, "| {} |", rule.join(" | "))?;
+        self.started = true;
+        Ok(())
+    }
+}
+
+impl RowWriter for HtmlWriter {
+    fn write_row(&mut self, row: &Map<String, Value>) -> Result<()> {
+        if !self.started {
+            if self.headers.is_empty() {
+                self.headers = row.keys().cloned().collect();
+            }
+            self.start()?;
+        }
+        let cells = self
+            .headers
+            .iter()
+            .map(|h| html_escape(&row.get(h).map(value_text).unwrap_or_default()));
+        writeln!(
+            self.writer,
+            "<tr><td>{}</td></tr>",
+            cells.collect::<Vec<_>>().join("</td><td>")
+        )?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // an empty result is still a table, headless if the columns are not known
+        if !self.started {
+            self.start()?;
+        }
+        self.writer.write_all(b"</tbody>\n</table>\n")?;
+        if self.page {
+            self.writer.write_all(HTML_PAGE_TAIL.as_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl HtmlWriter {
+    /// the page head, the table and its header row
+    fn start(&mut self) -> Result<()> {
+        if self.page {
+            self.writer.write_all(HTML_PAGE_HEAD.as_bytes())?;
+        }
+        self.writer.write_all(b"<table>\n<thead>\n")?;
+        if !self.headers.is_empty() {
+            let headers = self.headers.iter().map(|h| html_escape(h));
+            writeln!(
+                self.writer,
+                "<tr><th>{}</th></tr>",
+                headers.collect::<Vec<_>>().join("</th><th>")
+            )?;
+        }
+        self.writer.write_all(b"</thead>\n<tbody>\n")?;
+        self.started = true;
+        Ok(())
+    }
+}
+
+/// escape the text of a Markdown table cell, a cell has to stay on one line
+fn markdown_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace(['\n', '\r'], "<br>")
+}
+
+fn html_escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Convert a JSON value to a TOML value, returning an error for values TOML cannot represent
+/// (null, and integers that do not fit in an i64).
+fn json_to_toml(value: &Value) -> Result<toml::Value> {
+    let ret = match value {
+        Value::Null => bail!("TOML cannot represent null values"),
+        Value::Bool(b) => toml::Value::Boolean(*b),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                toml::Value::Integer(i)
+            } else if n.is_u64() {
+                bail!("integer {} is out of range for TOML", n)
+            } else {
+                // as_f64 always succeeds for numbers that are neither i64 nor u64
+                toml::Value::Float(n.as_f64().unwrap_or_default())
+            }
+        }
+        Value::String(s) => toml::Value::String(s.clone()),
+        Value::Array(items) => toml::Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| json_to_toml(item).with_context(|| format!("index {}", i)))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => toml::Value::Table(
+            map.iter()
+                .map(|(k, v)| {
+                    let v = json_to_toml(v).with_context(|| format!("column {:?}", k))?;
+                    Ok((k.clone(), v))
+                })
+                .collect::<Result<_>>()?,
+        ),
+    };
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{cell::RefCell, io, rc::Rc};
+
+    /// a Write that can still be read after the writer is boxed and consumed
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_rows(format: OutputFormat, rows: &[Value]) -> Result<String> {
+        write_table(format, &[], rows)
+    }
+
+    fn write_table(format: OutputFormat, headers: &[&str], rows: &[Value]) -> Result<String> {
+        let buf = SharedBuf::default();
+        let headers = headers.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        let mut writer = create_row_writer(format, Box::new(buf.clone()), &headers);
+        for row in rows {
+            writer.write_row(row.as_object().unwrap())?;
+        }
+        writer.finish()?;
+        let content = buf.0.borrow().clone();
+        Ok(String::from_utf8(content)?)
+    }
+
+    #[test]
+    fn test_streamed_output_matches_serializers() {
+        let rows = vec![
+            json!({"Name": "Dennis Harris", "Kit Number": 17, "Tags": ["a"]}),
+            json!({"Name": "Glenn Keller", "Kit Number": null, "Tags": []}),
+        ];
+        assert_eq!(
+            write_rows(OutputFormat::Json, &rows).unwrap(),
+            serde_json::to_string_pretty(&rows).unwrap()
+        );
+        assert_eq!(
+            write_rows(OutputFormat::Yaml, &rows).unwrap(),
+            serde_yaml::to_string(&rows).unwrap()
+        );
+        assert_eq!(write_rows(OutputFormat::Json, &[]).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let rows = vec![json!({"a": 1}), json!({"a": "x"})];
+        assert_eq!(
+            write_rows(OutputFormat::Ndjson, &rows).unwrap(),
+            "{\"a\":1}\n{\"a\":\"x\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_toml_quotes_keys() {
+        let rows = vec![json!({"Name": "Dennis Harris", "Kit Number": "17"})];
+        let content = write_rows(OutputFormat::Toml, &rows).unwrap();
+        assert_eq!(
+            content,
+            "[[rows]]\nName = \"Dennis Harris\"\n\"Kit Number\" = \"17\"\n"
+        );
+    }
+
+    #[test]
+    fn test_toml_rejects_null() {
+        let rows = vec![json!({"Name": "a"}), json!({"Name": null})];
+        let err = format!("{:#}", write_rows(OutputFormat::Toml, &rows).unwrap_err());
+        assert_eq!(
+            err,
+            "row 2: column \"Name\": TOML cannot represent null values"
+        );
+    }
+
+    #[test]
+    fn test_markdown_table() {
+        let rows = vec![
+            json!({"Name": "a|b", "Kit Number": 17}),
+            json!({"Name": "two\nlines", "Kit Number": null}),
+        ];
+        assert_eq!(
+            write_rows(OutputFormat::Markdown, &rows).unwrap(),
+            "| Name | Kit Number |\n| --- | --- |\n| a\\|b | 17 |\n| two<br>lines |  |\n"
+        );
+
+        // an empty result still has its header when the columns are known
+        assert_eq!(
+            write_table(OutputFormat::Markdown, &["Name", "Kit Number"], &[]).unwrap(),
+            "| Name | Kit Number |\n| --- | --- |\n"
+        );
+        assert_eq!(write_rows(OutputFormat::Markdown, &[]).unwrap(), "");
+        assert_eq!(
+            write_table(OutputFormat::Html, &["Name"], &[]).unwrap(),
+            "<table>\n<thead>\n<tr><th>Name</th></tr>\n</thead>\n<tbody>\n</tbody>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn test_html_table() {
+        let rows = vec![json!({"Name": "<b>Tom & \"Jerry\"</b>", "Kit": 1})];
+        assert_eq!(
+            write_rows(OutputFormat::Html, &rows).unwrap(),
+            "<table>\n<thead>\n<tr><th>Name</th><th>Kit</th></tr>\n</thead>\n<tbody>\n\
+             <tr><td>&lt;b&gt;Tom &amp; &quot;Jerry&quot;&lt;/b&gt;</td><td>1</td></tr>\n\
+             </tbody>\n</table>\n"
+        );
+
+        let buf = SharedBuf::default();
+        let writer = create_html_writer(Box::new(buf.clone()), &[], true);
+        writer.finish().unwrap();
+        let content = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        assert!(content.starts_with("<!DOCTYPE html>"));
+        assert!(
+            content.contains("<table>\n<thead>\n</thead>\n<tbody>\n</tbody>\n</table>\n<script>")
+        );
+    }
+}
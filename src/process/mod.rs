@@ -1,9 +1,40 @@
 mod b64;
 mod csv_convert;
+mod csv_diff;
+mod csv_expr;
+mod csv_gen;
+mod csv_join;
+mod csv_mask;
+mod csv_project;
+mod csv_query;
+mod csv_reader;
+mod csv_reverse;
+mod csv_schema;
+mod csv_show;
+mod csv_sort;
+mod csv_split;
+mod csv_stats;
+mod csv_table;
+mod csv_types;
+mod csv_unflatten;
+mod csv_validate;
+mod csv_writer;
 mod gen_pass;
 mod text;
 
 pub use b64::{process_decode, process_encode};
-pub use csv_convert::process_csv;
+pub use csv_convert::{process_csv, ConvertSummary};
+pub use csv_diff::{process_csv_diff, DiffSummary};
+pub use csv_gen::process_csv_gen;
+pub use csv_join::process_csv_join;
+pub use csv_mask::process_csv_mask;
+pub use csv_query::process_csv_query;
+pub use csv_reverse::process_csv_reverse;
+pub use csv_schema::process_csv_schema;
+pub use csv_show::process_csv_show;
+pub use csv_sort::{process_csv_dedup, process_csv_sort};
+pub use csv_split::process_csv_split;
+pub use csv_stats::{process_csv_stats, ColumnStats, TopValue};
+pub use csv_validate::{process_csv_validate, ValidationReport, Violation};
 pub use gen_pass::process_genpass;
 pub use text::{process_text_generate, process_text_sign, process_text_verify};
@@ -1,4 +1,3 @@
-use clap::builder::Str;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
 use std::path::Path;
@@ -9,7 +8,7 @@ use anyhow::{Ok, Result};
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine as _;
 
-use super::{gen_pass, process_genpass};
+use super::process_genpass;
 
 /// &[u8] implements Read, so we can use &[u8] as the reader when we do testing
 /// Sign the data from the reader and return the signature
@@ -21,7 +20,7 @@ pub trait TextVerify {
     fn verify(&self, reader: impl Read, sig: &[u8]) -> Result<bool>;
 }
 
-pub trait keyLoader {
+pub trait KeyLoader {
     // where means that the trait is only implemented for types that have a size
     // in here, Slef should be a type that has a size
     fn load(path: impl AsRef<Path>) -> Result<Self>
@@ -136,21 +135,21 @@ impl TextVerify for Ed25519Verifier {
     }
 }
 
-impl keyLoader for Blake3 {
+impl KeyLoader for Blake3 {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
         let key = fs::read(path)?;
         Self::try_new(&key)
     }
 }
 
-impl keyLoader for Ed25519Signer {
+impl KeyLoader for Ed25519Signer {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
         let key = fs::read(path)?;
         Self::try_new(&key)
     }
 }
 
-impl keyLoader for Ed25519Verifier {
+impl KeyLoader for Ed25519Verifier {
     fn load(path: impl AsRef<Path>) -> Result<Self> {
         let key = fs::read(path)?;
         Self::try_new(&key)
@@ -181,7 +180,9 @@ impl Blake3 {
     }
 
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
+        let Some(key) = key.get(..32) else {
+            anyhow::bail!("a blake3 key has 32 bytes, got {}", key.len());
+        };
         let key: [u8; 32] = key.try_into()?;
         let signer = Blake3::new(key);
         Ok(signer)
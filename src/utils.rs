@@ -1,6 +1,12 @@
-use std::{fs::File, io::Read};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    process::{Command, Stdio},
+};
 
 use anyhow::Result;
+use terminal_size::{terminal_size, Height, Width};
 
 pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -10,3 +16,51 @@ pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     };
     Ok(reader)
 }
+
+/// get_writer is the counterpart of [`get_reader`], if output is "-", it will write to stdout.
+/// the writer is buffered, so callers have to flush it when they are done.
+pub fn get_writer(output: &str) -> Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(std::io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(output)?))
+    };
+    Ok(writer)
+}
+
+/// the width of the terminal stdout is attached to, None if stdout is not a terminal
+pub fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_size().map(|(Width(w), _)| w as usize)
+}
+
+/// write_paged prints the content, through `$PAGER` (or `less`) when stdout is a terminal and the
+/// content is longer than the screen. If the pager cannot be started the content is printed as is.
+pub fn write_paged(content: &str) -> Result<()> {
+    let stdout = io::stdout();
+    let height = terminal_size().map(|(_, Height(h))| h as usize);
+    if stdout.is_terminal() && height.is_some_and(|h| content.lines().count() >= h) {
+        let pager = env::var("PAGER").unwrap_or_else(|_| "less -FRSX".into());
+        let mut args = pager.split_whitespace();
+        if let Some(program) = args.next() {
+            let child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .spawn();
+            if let Ok(mut child) = child {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // the pager closes its input when it is quit before the end, that is not an error
+                    let _ = stdin.write_all(content.as_bytes());
+                }
+                child.wait()?;
+                return Ok(());
+            }
+        }
+    }
+    let mut stdout = stdout.lock();
+    stdout.write_all(content.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}